@@ -1,17 +1,24 @@
-use crate::Args;
-use image::{DynamicImage, ImageOutputFormat, GrayImage, GenericImage, Luma};
-use image_dds::{image_from_dds};
+use crate::forward::{flip_signed_endpoints, from_signed, long_path, probe_writable, same_dir, write_atomically};
+use crate::contact_sheet;
+use crate::info::is_cubemap;
+use crate::slots::{Suffixes, TextureType, SLOTS};
+use crate::BackwardArgs;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ImageEncoder, ImageOutputFormat, GrayImage, Luma, RgbaImage};
+use image_dds::ddsfile::{Dds, DxgiFormat};
+use image_dds::image_from_dds;
+use rayon::prelude::*;
 use std::{
     collections::HashMap,
     path::{Path, PathBuf}, fs::File,
 };
 
-fn get_dds_file_paths<P>(path: P) -> std::io::Result<HashMap<String, PathBuf>>
+pub(crate) fn get_dds_file_paths<P>(path: P) -> std::io::Result<HashMap<String, PathBuf>>
 where
     P: AsRef<Path> + std::fmt::Debug,
 {
     // Get a list of all entries in the folder
-    let entries = std::fs::read_dir(path)?;
+    let entries = std::fs::read_dir(long_path(path.as_ref()))?;
     // Extract the filenames from the directory entries and store them in a vector
     let file_names: HashMap<String, PathBuf> = entries
         .filter_map(|entry| {
@@ -33,19 +40,293 @@ where
     Ok(file_names)
 }
 
-fn create_images(name: String, path: PathBuf) -> Vec<(String, DynamicImage)> {
-    let tex = match image_dds::ddsfile::Dds::read(File::open(path.clone()).unwrap()){
-        Ok(t) => t,
-        Err(e) => {println!("Error, can't read dds at {}: {}", path.display(), e); return vec![];},
+/// Image formats the backward conversion can write.
+#[derive(Clone, Copy)]
+pub enum OutputFormat {
+    Png,
+    Tga,
+    Bmp,
+    Tiff,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "tga" => Ok(OutputFormat::Tga),
+            "bmp" => Ok(OutputFormat::Bmp),
+            "tif" | "tiff" => Ok(OutputFormat::Tiff),
+            _ => Err(format!("unknown image format {}, expected png, tga, bmp or tiff", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Tga => "tga",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Tiff => "tiff",
+        }
+    }
+
+    fn image_format(&self) -> ImageOutputFormat {
+        match self {
+            OutputFormat::Png => ImageOutputFormat::Png,
+            OutputFormat::Tga => ImageOutputFormat::Tga,
+            OutputFormat::Bmp => ImageOutputFormat::Bmp,
+            OutputFormat::Tiff => ImageOutputFormat::Tiff,
+        }
+    }
+}
+
+/// Compression effort of png outputs.
+#[derive(Clone, Copy, Default)]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl std::str::FromStr for PngCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fast" => Ok(PngCompression::Fast),
+            "default" => Ok(PngCompression::Default),
+            "best" => Ok(PngCompression::Best),
+            _ => Err(format!("unknown png compression {}, expected fast, default or best", s)),
+        }
+    }
+}
+
+impl PngCompression {
+    fn compression_type(&self) -> CompressionType {
+        match self {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        }
+    }
+}
+
+/// Splits the images into rows x columns equal tiles, given as RxC.
+#[derive(Clone, Copy)]
+pub struct SplitGrid {
+    rows: u32,
+    columns: u32,
+}
+
+impl std::str::FromStr for SplitGrid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid grid {}, expected rows x columns like 4x4", s);
+        let lower = s.to_lowercase();
+        let (rows, columns) = lower.split_once('x').ok_or_else(invalid)?;
+        match (rows.trim().parse::<u32>(), columns.trim().parse::<u32>()) {
+            (Ok(rows), Ok(columns)) if rows > 0 && columns > 0 => Ok(SplitGrid { rows, columns }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The cubemap faces in the order they are stored in.
+const FACES: [&str; 6] = ["posx", "negx", "posy", "negy", "posz", "negz"];
+
+/// Cuts the decoded layers of arrays and cubemaps, which are stacked from top to bottom, into separate images.
+/// Cubemap faces are named name_posx, name_negx, ..., with the cube index before them in cubemap arrays, other layers name_0, name_1, ...
+fn split_layers(name: &str, img: RgbaImage, layers: u32, cubemap: bool) -> Vec<(String, RgbaImage)> {
+    if layers == 1 {
+        return vec![(name.to_owned(), img)];
+    }
+    if cubemap && !layers.is_multiple_of(6) {
+        log!("Warning: {} is a cubemap with {} layers instead of 6 per cube, the layers are written as numbered images.", name, layers);
+    }
+    let cubemap = cubemap && layers.is_multiple_of(6);
+    let (w, h) = img.dimensions();
+    let layer_h = h / layers;
+    (0..layers)
+        .map(|layer| {
+            let layer_name = match cubemap {
+                true if layers == 6 => format!("{}_{}", name, FACES[layer as usize]),
+                true => format!("{}_{}_{}", name, layer / 6, FACES[layer as usize % 6]),
+                false => format!("{}_{}", name, layer),
+            };
+            (layer_name, image::imageops::crop_imm(&img, 0, layer * layer_h, w, layer_h).to_image())
+        })
+        .collect()
+}
+
+/// Cuts the image into the tiles of the grid, named name_row_column. Rows and columns start at 0.
+fn split_tiles(name: &str, img: RgbaImage, grid: SplitGrid) -> Vec<(String, RgbaImage)> {
+    let (w, h) = img.dimensions();
+    if w % grid.columns != 0 || h % grid.rows != 0 {
+        log!(
+            "Warning: {}x{} of {} doesn't divide into {}x{} tiles evenly, the remaining pixels at the right and bottom are dropped.",
+            w, h, name, grid.rows, grid.columns
+        );
+    }
+    let (tile_w, tile_h) = (w / grid.columns, h / grid.rows);
+    if tile_w == 0 || tile_h == 0 {
+        error!("create", Some(name), "Error, {} is too small for {}x{} tiles!", name, grid.rows, grid.columns);
+        return vec![];
+    }
+    let mut res = Vec::new();
+    for r in 0..grid.rows {
+        for c in 0..grid.columns {
+            let tile = image::imageops::crop_imm(&img, c * tile_w, r * tile_h, tile_w, tile_h).to_image();
+            res.push((format!("{}_{}_{}", name, r, c), tile));
+        }
+    }
+    res
+}
+
+fn write_image(img: &DynamicImage, file: &mut File, args: &BackwardArgs) -> image::ImageResult<()> {
+    match args.format {
+        OutputFormat::Png => PngEncoder::new_with_quality(file, args.png_compression.compression_type(), FilterType::Adaptive)
+            .write_image(img.as_bytes(), img.width(), img.height(), img.color()),
+        _ => img.write_to(file, args.format.image_format()),
+    }
+}
+
+/// Textures with the diffuse suffix (empty unless overridden) are taken for diffuse textures,
+/// unless they end with the longer suffix of another slot.
+fn is_diffuse(name: &str, suffixes: &Suffixes) -> bool {
+    let diffuse = suffixes.get(TextureType::Diffuse);
+    name.ends_with(diffuse)
+        && !SLOTS.iter().filter(|s| s.texture != TextureType::Diffuse).any(|s| {
+            let suffix = suffixes.get(s.texture);
+            !suffix.is_empty() && suffix.len() >= diffuse.len() && name.ends_with(suffix)
+        })
+}
+
+fn read_dds(path: &Path) -> Option<Dds> {
+    let file = match File::open(long_path(path)) {
+        Ok(f) => f,
+        Err(e) => {error!("load", Some(path.display().to_string().as_str()), "Error, can't open {}: {}", path.display(), e); return None;},
     };
-    let img = match image_from_dds(&tex, 0){
+    match Dds::read(file) {
+        Ok(t) => Some(t),
+        Err(e) => {error!("load", Some(path.display().to_string().as_str()), "Error, can't read dds at {}: {}", path.display(), e); None},
+    }
+}
+
+fn decode_mip(name: &str, mut tex: Dds, mip: u32) -> Option<RgbaImage> {
+    // the decoder reads the blocks as unsigned, so the endpoints are switched back to the offset encoding of --signed
+    let signed = matches!(tex.get_dxgi_format(), Some(DxgiFormat::BC4_SNorm | DxgiFormat::BC5_SNorm));
+    if signed {
+        flip_signed_endpoints(&mut tex.data);
+    }
+    let mut img = match image_from_dds(&tex, mip){
         Ok(img) => img,
-        Err(e) => {println!("Error, can't tranform dds to image: {}", e); return vec![];},
+        Err(e) => {error!("load", Some(name), "Error, can't tranform dds to image: {}", e); return None;},
+    };
+    if signed {
+        from_signed(&mut img);
+    }
+    Some(img)
+}
+
+fn create_images(name: String, path: PathBuf, suffixes: &Suffixes, args: &BackwardArgs) -> Vec<(String, DynamicImage)> {
+    let Some(tex) = read_dds(&path) else {
+        return vec![];
     };
+    if args.mip >= tex.get_num_mipmap_levels() {
+        error!(
+            "load",
+            Some(path.display().to_string().as_str()),
+            "Error, {} has only {} mipmaps, can't extract mipmap {}!",
+            path.display(),
+            tex.get_num_mipmap_levels(),
+            args.mip
+        );
+        return vec![];
+    }
+    let cubemap = is_cubemap(&tex);
+    let layers = if cubemap && tex.header10.is_some() { tex.get_num_array_layers() * 6 } else { tex.get_num_array_layers() };
+    let Some(img) = decode_mip(&name, tex, args.mip) else {
+        return vec![];
+    };
+    split_layers(&name, img, layers.max(1), cubemap)
+        .into_iter()
+        .flat_map(|(layer_name, img)| match args.split_grid {
+            Some(grid) => split_tiles(&layer_name, img, grid),
+            None => vec![(layer_name, img)],
+        })
+        .flat_map(|(part_name, img)| split_alpha(&name, part_name, img, suffixes, args))
+        .collect()
+}
+
+/// The thumbnail of the contact sheet, decoded from the smallest mipmap that is still at least the thumbnail size.
+fn create_thumbnail(name: &str, path: &Path, size: u32) -> Option<RgbaImage> {
+    thumbnail(name, read_dds(path)?, size)
+}
+
+/// Decodes the texture downscaled so its longer side is `size` pixels.
+pub(crate) fn thumbnail(name: &str, tex: Dds, size: u32) -> Option<RgbaImage> {
+    let longer = tex.get_width().max(tex.get_height());
+    let mip = (0..tex.get_num_mipmap_levels())
+        .take_while(|level| (longer >> level).max(1) >= size)
+        .last()
+        .unwrap_or(0);
+    let img = decode_mip(name, tex, mip)?;
+    let scale = size as f64 / img.width().max(img.height()) as f64;
+    let (w, h) = ((img.width() as f64 * scale).round().max(1.0) as u32, (img.height() as f64 * scale).round().max(1.0) as u32);
+    Some(image::imageops::thumbnail(&img, w, h))
+}
+
+/// Writes the thumbnails of all textures into a single contact_sheet.png.
+fn write_contact_sheet(paths: HashMap<String, PathBuf>, args: &BackwardArgs, out_dir: &Path) -> bool {
+    if args.thumb_size < contact_sheet::MIN_SIZE {
+        error!("setup", None, "Critical error, the thumbnail size must be at least {} to fit the labels!", contact_sheet::MIN_SIZE);
+        return false;
+    }
+    let mut thumbs: Vec<(String, RgbaImage)> = paths
+        .into_par_iter()
+        .filter_map(|(name, path)| {
+            let label = path.file_name()?.to_string_lossy().into_owned();
+            Some((label, crate::log::grouped(|| create_thumbnail(&name, &path, args.thumb_size))?))
+        })
+        .collect();
+    thumbs.sort_by(|a, b| a.0.cmp(&b.0));
+    let sheet = DynamicImage::ImageRgba8(contact_sheet::compose(&thumbs, args.thumb_size, args.sheet_columns));
+    let out_path = out_dir.join(args.name.clone() + "contact_sheet.png");
+    log!("Writing: {} ({} textures)", out_path.display(), thumbs.len());
+    let written = write_atomically(&out_path, |file| {
+        PngEncoder::new_with_quality(file, args.png_compression.compression_type(), FilterType::Adaptive)
+            .write_image(sheet.as_bytes(), sheet.width(), sheet.height(), sheet.color())
+            .map_err(|e| e.to_string())
+    });
+    if let Err(e) = written {
+        error!("write", Some(out_path.display().to_string().as_str()), "Error, cannot write the contact sheet {}! {}", out_path.display(), e);
+        return false;
+    }
+    true
+}
+
+/// Whether enough pixels are transparent (further from 255 than --alpha-tolerance) for the alpha to be worth its own image.
+fn has_meaningful_alpha(img: &RgbaImage, args: &BackwardArgs) -> bool {
+    let opaque = 255 - args.alpha_tolerance;
+    let transparent = img.pixels().filter(|p| p.0[3] < opaque).count();
+    let pixels = (img.width() as usize * img.height() as usize).max(1);
+    transparent > 0 && transparent as f32 * 100.0 / pixels as f32 >= args.alpha_min_percent
+}
+
+/// The rgb image and, if the texture has transparency, the alpha as a separate image. `texture` is the name of the dds.
+fn split_alpha(texture: &str, name: String, img: RgbaImage, suffixes: &Suffixes, args: &BackwardArgs) -> Vec<(String, DynamicImage)> {
+    if args.rgba {
+        return vec![(name, DynamicImage::ImageRgba8(img))];
+    }
     let mut res: Vec<(String, DynamicImage)> = vec![];
     let rgb = DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(img.clone()).into_rgb8());
     res.push((name.clone(), rgb));
-    if !img.pixels().all(|p| p.0[3] == 255){
+    if has_meaningful_alpha(&img, args) {
         let mut a = GrayImage::new(img.width(), img.height());
         for y in 0..img.height() {
             for x in 0..img.width() {
@@ -53,28 +334,57 @@ fn create_images(name: String, path: PathBuf) -> Vec<(String, DynamicImage)> {
                 a.put_pixel(x, y, Luma([p.0[3]])); // set height.r to result.a
             }
         }
-        res.push((name + "_alpha", DynamicImage::ImageLuma8(a)));
+        let alpha_suffix = if args.terrain_parallax && is_diffuse(texture, suffixes) { "_height" } else { "_alpha" };
+        res.push((name + alpha_suffix, DynamicImage::ImageLuma8(a)));
     }
-    return res;
+    res
 }
 
 
-pub fn run_backward(args: &Args, in_dir: &PathBuf, out_dir: &PathBuf) {
-    let paths = get_dds_file_paths(in_dir).unwrap();
-    let mut images = vec![];
-    for (name, path) in paths {
-        images.extend_from_slice(&create_images(name, path));
+/// Returns false when there was nothing to convert or a critical error stopped the conversion.
+pub fn run_backward(args: &BackwardArgs, in_dir: &Path, out_dir: &Path) -> bool {
+    let suffixes = match Suffixes::new(&args.suffix) {
+        Ok(s) => s,
+        Err(e) => {error!("setup", None, "Critical error, {}", e); return false;},
+    };
+    let paths = match get_dds_file_paths(in_dir) {
+        Ok(paths) => paths,
+        Err(e) => {error!("setup", None, "Critical error, cannot get file paths: {}", e); return false;},
+    };
+    if paths.is_empty() {
+        error!("setup", None, "Critical error, no dds files found in {}!", in_dir.display());
+        return false;
+    }
+    if same_dir(in_dir, out_dir) {
+        // only dds files are read, so the written images are never converted again
+        log!("Warning: The output directory is the input directory, the images are written next to the textures.");
     }
+    if let Err(e) = probe_writable(out_dir) {
+        error!("write", Some(out_dir.display().to_string().as_str()), "Critical error, the output directory {} is not writable: {}", out_dir.display(), e);
+        return false;
+    }
+    if args.contact_sheet {
+        return write_contact_sheet(paths, args, out_dir);
+    }
+    // Decoding and png encoding are independent per file, only the writes stay sequential.
+    let mut images: Vec<(String, DynamicImage)> = paths
+        .into_par_iter()
+        .flat_map(|(name, path)| crate::log::grouped(|| create_images(name, path, &suffixes, args)))
+        .collect();
+    images.sort_by(|a, b| a.0.cmp(&b.0));
     for (name, img) in images {
-        let out_path = out_dir.join(args.name.clone() + name.as_str() + ".png");
-        println!("Writing: {}", out_path.display());
-        let mut file = match File::create(out_path){
-            Ok(f) => f,
-            Err(e) => {println!("Error, cannot create texture file at {}! {}", out_dir.display(), e); continue;},
-        };
-        if let Err(e) = img.write_to(&mut file, ImageOutputFormat::Png){
-            println!("Error, cannot write into texture file! {}", e);
+        let out_path = out_dir.join(args.name.clone() + name.as_str() + "." + args.format.extension());
+        log!("Writing: {}", out_path.display());
+        let written = write_atomically(&out_path, |file| {
+            write_image(&img, file, args).map_err(|e| e.to_string())
+        });
+        if let Err(e) = written {
+            error!("write", Some(out_path.display().to_string().as_str()), "Error, cannot write the image file {}! {}", out_path.display(), e);
+            if let Err(e) = probe_writable(out_dir) {
+                error!("write", Some(out_dir.display().to_string().as_str()), "Critical error, the output directory {} is no longer writable: {}. The remaining images are not written!", out_dir.display(), e);
+                return false;
+            }
         }
     }
+    true
 }
-
@@ -0,0 +1,82 @@
+//! Encodes one image in the common formats and qualities and reports the size and quality of each, to help choose the options.
+use image::RgbaImage;
+use image_dds::{image_from_dds, ImageFormat};
+use std::path::Path;
+
+use crate::forward::{dds_bytes, encode, long_path, EncodeQuality};
+use crate::{BenchArgs, ForwardArgs};
+
+const FORMATS: [(&str, ImageFormat); 3] = [
+    ("BC1", ImageFormat::BC1Unorm),
+    ("BC3", ImageFormat::BC3Unorm),
+    ("BC7", ImageFormat::BC7Unorm),
+];
+
+const QUALITIES: [(&str, EncodeQuality); 3] = [
+    ("fast", EncodeQuality::Fast),
+    ("normal", EncodeQuality::Normal),
+    ("slow", EncodeQuality::Slow),
+];
+
+/// Peak signal to noise ratio of the channels in dB, infinite for identical images.
+fn psnr(expected: &RgbaImage, actual: &RgbaImage, channels: std::ops::Range<usize>) -> f64 {
+    let mut sum = 0.0;
+    for (p, q) in expected.pixels().zip(actual.pixels()) {
+        for c in channels.clone() {
+            let d = p.0[c] as f64 - q.0[c] as f64;
+            sum += d * d;
+        }
+    }
+    let mse = sum / (expected.pixels().len() * channels.len()) as f64;
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+pub fn run_bench(args: &BenchArgs, in_dir: &Path) -> bool {
+    let path = if args.image.is_absolute() || args.image.exists() {
+        args.image.clone()
+    } else {
+        in_dir.join(&args.image)
+    };
+    let source = match image::open(long_path(&path)) {
+        Ok(img) => img.to_rgba8(),
+        Err(e) => {
+            error!("load", Some(path.display().to_string().as_str()), "Critical error, cannot open {}: {}", path.display(), e);
+            return false;
+        }
+    };
+    let has_alpha = source.pixels().any(|p| p.0[3] != 255);
+    log!("Encoding {} ({}x{}), the quality only changes BC7 with the current encoders.", path.display(), source.width(), source.height());
+    println!("format\tquality\tsize\tpsnr rgb\tpsnr alpha");
+    for (format_name, format) in FORMATS {
+        for (quality_name, quality) in QUALITIES {
+            let encode_args = ForwardArgs {
+                quality_bc7: quality,
+                quality_other: quality,
+                ..Default::default()
+            };
+            let Some(tex) = encode(&source, format, None, has_alpha, &encode_args) else {
+                continue;
+            };
+            let decoded = match image_from_dds(&tex, 0) {
+                Ok(img) => img,
+                Err(e) => {
+                    error!("verify", None, "Error, cannot decode the {} texture: {}", format_name, e);
+                    continue;
+                }
+            };
+            let alpha = if has_alpha { format!("{:.2} dB", psnr(&source, &decoded, 3..4)) } else { String::from("-") };
+            println!(
+                "{}\t{}\t{}\t{:.2} dB\t{}",
+                format_name,
+                quality_name,
+                dds_bytes(&tex).len(),
+                psnr(&source, &decoded, 0..3),
+                alpha
+            );
+        }
+    }
+    true
+}
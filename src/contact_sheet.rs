@@ -0,0 +1,117 @@
+//! The contact sheet of the backward conversion: a grid of thumbnails labeled with their file names.
+use image::{Rgba, RgbaImage};
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// The smallest thumbnail size that is wide enough for a glyph of the labels.
+pub(crate) const MIN_SIZE: u32 = GLYPH_WIDTH;
+/// The glyph and the space to the next one.
+const ADVANCE: u32 = GLYPH_WIDTH + 1;
+const LABEL_HEIGHT: u32 = GLYPH_HEIGHT + 4;
+const SPACING: u32 = 4;
+const BACKGROUND: Rgba<u8> = Rgba([32, 32, 32, 255]);
+const TEXT: Rgba<u8> = Rgba([230, 230, 230, 255]);
+
+const GLYPH_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_-.?";
+/// A 5x7 pixel font, one byte per row with the leftmost pixel in bit 4. Lowercase letters use the uppercase glyphs.
+const GLYPHS: [[u8; 7]; 40] = [
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+];
+
+/// The glyph of the character, characters the font doesn't have are drawn as ?. None for a space.
+fn glyph(c: char) -> Option<&'static [u8; 7]> {
+    if c == ' ' {
+        return None;
+    }
+    let index = GLYPH_CHARS.find(c.to_ascii_uppercase()).unwrap_or(GLYPH_CHARS.len() - 1);
+    Some(&GLYPHS[index])
+}
+
+/// Draws the text from (x, y), cut off at max_width pixels with a trailing . to show it is incomplete.
+/// Without room for that, a text that doesn't fit is left out.
+fn draw_text(sheet: &mut RgbaImage, text: &str, x: u32, y: u32, max_width: u32) {
+    let fits = (max_width / ADVANCE) as usize;
+    let mut chars: Vec<char> = text.chars().collect();
+    if chars.len() > fits && fits < 2 {
+        return;
+    }
+    if chars.len() > fits {
+        chars.truncate(fits.saturating_sub(2));
+        chars.extend([' ', '.']);
+    }
+    for (i, c) in chars.into_iter().enumerate() {
+        let Some(rows) = glyph(c) else {
+            continue;
+        };
+        let left = x + i as u32 * ADVANCE;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                let (px, py) = (left + column, y + row as u32);
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 && px < sheet.width() && py < sheet.height() {
+                    sheet.put_pixel(px, py, TEXT);
+                }
+            }
+        }
+    }
+}
+
+/// Arranges the labeled thumbnails in rows of `columns` cells of `size` x `size` pixels, each thumbnail centered in its cell.
+/// The thumbnails are drawn opaque, the alpha would hide the color of packed textures.
+pub(crate) fn compose(thumbs: &[(String, RgbaImage)], size: u32, columns: u32) -> RgbaImage {
+    let columns = columns.clamp(1, thumbs.len().max(1) as u32);
+    let rows = (thumbs.len() as u32).div_ceil(columns);
+    let (cell_w, cell_h) = (size + SPACING, size + LABEL_HEIGHT + SPACING);
+    let mut sheet = RgbaImage::from_pixel(columns * cell_w + SPACING, rows * cell_h + SPACING, BACKGROUND);
+    for (i, (label, thumb)) in thumbs.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let (left, top) = (SPACING + column * cell_w, SPACING + row * cell_h);
+        let x = left + (size - thumb.width().min(size)) / 2;
+        let y = top + (size - thumb.height().min(size)) / 2;
+        for (tx, ty, p) in thumb.enumerate_pixels() {
+            if tx < size && ty < size {
+                sheet.put_pixel(x + tx, y + ty, Rgba([p.0[0], p.0[1], p.0[2], 255]));
+            }
+        }
+        draw_text(&mut sheet, label, left, top + size + 2, size);
+    }
+    sheet
+}
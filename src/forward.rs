@@ -1,20 +1,32 @@
 use image::{io::Reader as ImageReader, DynamicImage};
 use image::{GenericImage, GenericImageView, Rgba};
-use image_dds::ddsfile::Dds;
-use image_dds::{dds_from_image, ImageFormat};
+use image_dds::ddsfile::{D3DFormat, Dds, DxgiFormat, FourCC, NewD3dParams};
+use image_dds::{dds_from_image, image_from_dds, ImageFormat, SurfaceRgba8};
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::{Cursor, Write};
 use std::path::{Path, PathBuf};
-use crate::Args;
-enum ImageProps {
+use std::time::{Duration, Instant};
+use crate::ktx2::write_ktx2;
+use crate::settings::{Sidecar, SIDECAR_EXTENSION};
+use crate::slots::{Output, Suffixes, TextureType, INPUTS, SLOTS};
+use crate::ForwardArgs;
+
+/// What the texture has to preserve, decides the compression format.
+#[allow(clippy::upper_case_acronyms, dead_code)]
+pub enum ImageProps {
     Grayscale,
     RGB,
     RGBFullAlpha,
     RGBCutoutAlpha,
+    /// Only the red and green channels matter, such as X and Y of a normal map.
+    TwoChannel,
     Uncompressed,
 }
 
-struct InputImages {
+/// The loaded input images, each of them is optional.
+#[derive(Default)]
+pub struct InputImages {
     pub diffuse_alpha: Option<DynamicImage>,
     pub normal: Option<DynamicImage>,
     pub specular: Option<DynamicImage>,
@@ -29,25 +41,552 @@ struct InputImages {
     pub backlight: Option<DynamicImage>,
     pub metallic: Option<DynamicImage>,
     pub glossiness: Option<DynamicImage>,
+    pub roughness: Option<DynamicImage>,
+    pub ao: Option<DynamicImage>,
+    /// The --detail-normal blended into the normal map.
+    pub detail_normal: Option<DynamicImage>,
+    /// All mipmaps of the dds inputs by input name, only loaded with --keep-mips.
+    pub source_mips: HashMap<&'static str, SurfaceRgba8<Vec<u8>>>,
+}
+
+impl InputImages {
+    /// The image of the input name, see [INPUTS].
+    fn get(&self, name: &str) -> Option<&DynamicImage> {
+        match name {
+            "diffuse" => &self.diffuse_alpha,
+            "normal" => &self.normal,
+            "specular" => &self.specular,
+            "glow" => &self.glow,
+            "skin_tint" => &self.skin_tint,
+            "height" => &self.height,
+            "cubemap" => &self.cubemap,
+            "env_mask" => &self.env_mask,
+            "inner_diffuse" => &self.inner_diffuse,
+            "inner_depth" => &self.inner_depth,
+            "subsurface" => &self.subsurface,
+            "backlight" => &self.backlight,
+            "metallic" => &self.metallic,
+            "glossiness" => &self.glossiness,
+            "roughness" => &self.roughness,
+            "ao" => &self.ao,
+            _ => &None,
+        }
+        .as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, name: &str) -> Option<&mut Option<DynamicImage>> {
+        Some(match name {
+            "diffuse" => &mut self.diffuse_alpha,
+            "normal" => &mut self.normal,
+            "specular" => &mut self.specular,
+            "glow" => &mut self.glow,
+            "skin_tint" => &mut self.skin_tint,
+            "height" => &mut self.height,
+            "cubemap" => &mut self.cubemap,
+            "env_mask" => &mut self.env_mask,
+            "inner_diffuse" => &mut self.inner_diffuse,
+            "inner_depth" => &mut self.inner_depth,
+            "subsurface" => &mut self.subsurface,
+            "backlight" => &mut self.backlight,
+            "metallic" => &mut self.metallic,
+            "glossiness" => &mut self.glossiness,
+            "roughness" => &mut self.roughness,
+            "ao" => &mut self.ao,
+            _ => return None,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        [
+            &self.diffuse_alpha, &self.normal, &self.specular, &self.glow, &self.skin_tint,
+            &self.height, &self.cubemap, &self.env_mask, &self.inner_diffuse, &self.inner_depth,
+            &self.subsurface, &self.backlight, &self.metallic, &self.glossiness, &self.roughness,
+            &self.ao,
+        ]
+        .iter()
+        .all(|img| img.is_none())
+    }
+}
+
+/// A channel of the source image to read when packing grayscale data into another texture.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Channel {
+    #[default]
+    R,
+    G,
+    B,
+    A,
+}
+
+impl std::str::FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "r" | "red" => Ok(Channel::R),
+            "g" | "green" => Ok(Channel::G),
+            "b" | "blue" => Ok(Channel::B),
+            "a" | "alpha" => Ok(Channel::A),
+            _ => Err(format!("unknown channel {}, expected r, g, b or a", s)),
+        }
+    }
+}
+
+/// The file format textures are written in.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Container {
+    #[default]
+    Dds,
+    Ktx2,
+}
+
+impl std::str::FromStr for Container {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dds" => Ok(Container::Dds),
+            "ktx2" => Ok(Container::Ktx2),
+            _ => Err(format!("unknown container {}, expected dds or ktx2", s)),
+        }
+    }
+}
+
+impl Container {
+    fn extension(&self) -> &'static str {
+        match self {
+            Container::Dds => "dds",
+            Container::Ktx2 => "ktx2",
+        }
+    }
+}
+
+/// What the pitchOrLinearSize field of the dds header holds. The DDS documentation asks for the linear size (bytes of the
+/// top mipmap) on block compressed textures and the pitch (bytes per row) on uncompressed ones, which is what ddsfile writes.
+/// The games and DirectXTex compute the sizes themselves, only strict loaders check the field or its flag.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum PitchMode {
+    /// Keep the field ddsfile writes.
+    #[default]
+    Auto,
+    Pitch,
+    LinearSize,
+    /// Write 0 without either flag, as some older exporters do.
+    None,
+}
+
+impl std::str::FromStr for PitchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(PitchMode::Auto),
+            "pitch" => Ok(PitchMode::Pitch),
+            "linear" | "linear-size" => Ok(PitchMode::LinearSize),
+            "none" => Ok(PitchMode::None),
+            _ => Err(format!("unknown pitch mode {}, expected auto, pitch, linear-size or none", s)),
+        }
+    }
+}
+
+/// Offsets into the dds header, ddsfile keeps the flags private so the written bytes are patched.
+const HEADER_FLAGS_OFFSET: usize = 4;
+const PITCH_OFFSET: usize = 16;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+
+/// Where the --tag goes: the reserved fields of the header, after the signature. NVTT and GIMP write their own marks into
+/// the last two reserved fields, those are left alone.
+const TAG_OFFSET: usize = 28;
+const TAG_SIGNATURE: &[u8; 4] = b"DMC1";
+pub(crate) const TAG_LENGTH: usize = 32;
+
+/// The dds file of the texture with the pitch field as requested by --pitch-mode and the --tag in the reserved fields.
+pub(crate) fn dds_file_bytes(tex: &Dds, args: &ForwardArgs) -> Vec<u8> {
+    let mut bytes = dds_bytes(tex);
+    set_pitch(&mut bytes, tex, args.pitch_mode);
+    if let Some(tag) = &args.tag {
+        let at = 4 + TAG_OFFSET;
+        bytes[at..at + 4].copy_from_slice(TAG_SIGNATURE);
+        let tag = truncate_tag(tag).as_bytes();
+        bytes[at + 4..at + 4 + TAG_LENGTH].fill(0);
+        bytes[at + 4..at + 4 + tag.len()].copy_from_slice(tag);
+    }
+    bytes
+}
+
+/// The tag cut to the bytes the header has room for, at a character boundary.
+pub(crate) fn truncate_tag(tag: &str) -> &str {
+    let mut end = tag.len().min(TAG_LENGTH);
+    while !tag.is_char_boundary(end) {
+        end -= 1;
+    }
+    &tag[..end]
+}
+
+/// The --tag of a dds file written by this tool, None for other files.
+pub(crate) fn read_tag(file: &[u8]) -> Option<String> {
+    let at = 4 + TAG_OFFSET;
+    let field = file.get(at..at + 4 + TAG_LENGTH)?;
+    if &field[..4] != TAG_SIGNATURE {
+        return None;
+    }
+    let tag = &field[4..];
+    let end = tag.iter().position(|&b| b == 0).unwrap_or(tag.len());
+    Some(String::from_utf8_lossy(&tag[..end]).into_owned())
+}
+
+fn set_pitch(bytes: &mut [u8], tex: &Dds, mode: PitchMode) {
+    let (flag, value) = match (mode, tex.get_pitch()) {
+        (PitchMode::Auto, _) => return,
+        (PitchMode::Pitch, Some(pitch)) => (DDSD_PITCH, pitch),
+        (PitchMode::LinearSize, Some(pitch)) => (DDSD_LINEARSIZE, pitch * tex.get_height().div_ceil(tex.get_pitch_height())),
+        (PitchMode::None, _) => (0, 0),
+        (_, None) => {
+            log!("Warning: The pitch of {:?} is unknown, the header keeps its pitch field.", tex.get_dxgi_format());
+            return;
+        }
+    };
+    // the header starts after the 4 byte magic number
+    let flags_at = 4 + HEADER_FLAGS_OFFSET;
+    let mut flags = u32::from_le_bytes(bytes[flags_at..flags_at + 4].try_into().unwrap());
+    flags = (flags & !(DDSD_PITCH | DDSD_LINEARSIZE)) | flag;
+    bytes[flags_at..flags_at + 4].copy_from_slice(&flags.to_le_bytes());
+    let pitch_at = 4 + PITCH_OFFSET;
+    bytes[pitch_at..pitch_at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Returns the pixel index of `channel` in `img`, falling back to red when the channel carries no data.
+fn resolve_channel(name: &str, img: &DynamicImage, channel: Channel) -> usize {
+    let color = img.color();
+    match channel {
+        Channel::R => 0,
+        Channel::G | Channel::B if !color.has_color() => {
+            log!("Warning: {} is grayscale, using its gray channel.", name);
+            0
+        }
+        Channel::A if !color.has_alpha() => {
+            log!("Warning: {} has no alpha channel, using its red channel.", name);
+            0
+        }
+        Channel::G => 1,
+        Channel::B => 2,
+        Channel::A => 3,
+    }
+}
+
+/// A recognized input file, either on disk or read from a zip archive into memory.
+enum InputFile {
+    Disk(PathBuf),
+    Archived { name: String, data: Vec<u8> },
+}
+
+impl InputFile {
+    fn is_dds(&self) -> bool {
+        self.file_name().to_lowercase().ends_with(".dds")
+    }
+
+    fn is_sidecar(&self) -> bool {
+        self.file_name().to_lowercase().ends_with(SIDECAR_EXTENSION)
+    }
+
+    fn read_to_string(&self) -> Result<String, String> {
+        match self {
+            InputFile::Disk(path) => std::fs::read_to_string(long_path(path)).map_err(|e| e.to_string()),
+            InputFile::Archived { data, .. } => String::from_utf8(data.clone()).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn read_dds(&self) -> Result<Dds, String> {
+        match self {
+            InputFile::Disk(path) => {
+                let mut file = File::open(long_path(path)).map_err(|e| e.to_string())?;
+                Dds::read(&mut file).map_err(|e| e.to_string())
+            }
+            InputFile::Archived { data, .. } => Dds::read(&mut Cursor::new(data)).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Whether the file is a png with a color palette, the decoder expands those to rgb(a).
+    fn is_indexed_png(&self) -> bool {
+        const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+        let mut header = [0u8; 26];
+        let read = match self {
+            InputFile::Disk(path) => File::open(long_path(path))
+                .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header))
+                .is_ok(),
+            InputFile::Archived { data, .. } if data.len() >= 26 => {
+                header.copy_from_slice(&data[..26]);
+                true
+            }
+            InputFile::Archived { .. } => false,
+        };
+        // the color type is the 10th byte of the IHDR data
+        read && header.starts_with(SIGNATURE) && header[25] == 3
+    }
+
+    fn file_name(&self) -> String {
+        match self {
+            InputFile::Disk(path) => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            InputFile::Archived { name, .. } => name.clone(),
+        }
+    }
+
+    /// The resolution from the file header, without decoding the pixels.
+    fn dimensions(&self) -> Result<(u32, u32), String> {
+        if self.is_dds() {
+            // height and width follow the magic number, the header size and the flags
+            let mut header = [0u8; 20];
+            match self {
+                InputFile::Disk(path) => File::open(long_path(path))
+                    .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header))
+                    .map_err(|e| e.to_string())?,
+                InputFile::Archived { data, .. } if data.len() >= 20 => header.copy_from_slice(&data[..20]),
+                InputFile::Archived { .. } => return Err(String::from("the dds header is incomplete")),
+            }
+            let field = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+            return Ok((field(16), field(12)));
+        }
+        let dimensions = match self {
+            InputFile::Disk(path) => ImageReader::open(long_path(path))
+                .and_then(|r| r.with_guessed_format())
+                .map_err(|e| e.to_string())?
+                .into_dimensions(),
+            InputFile::Archived { data, .. } => ImageReader::new(Cursor::new(data.as_slice()))
+                .with_guessed_format()
+                .map_err(|e| e.to_string())?
+                .into_dimensions(),
+        };
+        dimensions.map_err(|e| e.to_string())
+    }
+
+    /// The number of frames of an animated gif, webp or png, 1 for still images and the other formats.
+    fn frame_count(&self) -> usize {
+        let Ok(format) = image::ImageFormat::from_path(self.file_name()) else {
+            return 1;
+        };
+        match self {
+            InputFile::Disk(path) => File::open(long_path(path)).map_or(1, |f| count_frames(std::io::BufReader::new(f), format)),
+            InputFile::Archived { data, .. } => count_frames(Cursor::new(data.as_slice()), format),
+        }
+    }
+}
+
+fn count_frames<R: std::io::Read>(reader: R, format: image::ImageFormat) -> usize {
+    use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+    use image::AnimationDecoder;
+    let count = match format {
+        image::ImageFormat::Gif => GifDecoder::new(reader).map(|d| d.into_frames().count()),
+        image::ImageFormat::WebP => WebPDecoder::new(reader).map(|d| match d.has_animation() {
+            true => d.into_frames().count(),
+            false => 1,
+        }),
+        // only the header is read for still pngs
+        image::ImageFormat::Png => PngDecoder::new(reader).map(|d| match d.is_apng() {
+            true => d.apng().into_frames().count(),
+            false => 1,
+        }),
+        _ => Ok(1),
+    };
+    count.unwrap_or(1)
+}
+
+fn is_zip(path: &Path) -> bool {
+    long_path(path).is_file() && path.extension().is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+const IGNORE_FILE: &str = ".dmcignore";
+
+/// Parses the ignore file: one glob pattern per line, empty lines and lines starting with # are skipped.
+fn parse_ignore_patterns(text: &str) -> Vec<glob::Pattern> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match glob::Pattern::new(line) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                log!("Warning: invalid pattern {} in {}: {}", line, IGNORE_FILE, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn is_ignored(patterns: &[glob::Pattern], file_name: &str) -> bool {
+    file_name == IGNORE_FILE || patterns.iter().any(|p| p.matches(file_name))
+}
+
+/// The game the textures are made for, it decides which formats may be used.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TargetGame {
+    /// Skyrim LE only reads the DX9 formats.
+    Le,
+    Se,
+    Fo4,
+}
+
+impl std::str::FromStr for TargetGame {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "le" => Ok(TargetGame::Le),
+            "se" => Ok(TargetGame::Se),
+            "fo4" => Ok(TargetGame::Fo4),
+            _ => Err(format!("unknown target game {}, expected le, se or fo4", s)),
+        }
+    }
+}
+
+impl TargetGame {
+    fn name(&self) -> &'static str {
+        match self {
+            TargetGame::Le => "Skyrim LE",
+            TargetGame::Se => "Skyrim SE",
+            TargetGame::Fo4 => "Fallout 4",
+        }
+    }
+
+    fn allows(&self, format: DxgiFormat) -> bool {
+        use DxgiFormat::*;
+        let dx9 = matches!(
+            format,
+            BC1_UNorm | BC1_UNorm_sRGB | BC2_UNorm | BC2_UNorm_sRGB | BC3_UNorm | BC3_UNorm_sRGB | R8G8B8A8_UNorm
+        );
+        match self {
+            TargetGame::Le => dx9,
+            TargetGame::Se | TargetGame::Fo4 => {
+                dx9 || matches!(format, BC4_UNorm | BC5_UNorm | BC7_UNorm | BC7_UNorm_sRGB)
+            }
+        }
+    }
+}
+
+/// How --square makes off-square inputs square.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SquareMode {
+    /// Cut the longer side down to the shorter one, keeping the center.
+    Crop,
+    /// Extend the shorter side to the longer one, see [pad_image].
+    Pad,
+}
+
+impl std::str::FromStr for SquareMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "crop" => Ok(SquareMode::Crop),
+            "pad" => Ok(SquareMode::Pad),
+            _ => Err(format!("unknown square mode {}, expected crop or pad", s)),
+        }
+    }
+}
+
+/// How file names are matched to the input names.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum MatchMode {
+    /// The file stem has to be the input name.
+    #[default]
+    Exact,
+    /// The input name has to appear somewhere in the file stem.
+    Contains,
+}
+
+impl std::str::FromStr for MatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "exact" => Ok(MatchMode::Exact),
+            "contains" => Ok(MatchMode::Contains),
+            _ => Err(format!("unknown match mode {}, expected exact or contains", s)),
+        }
+    }
+}
+
+/// Renames the files whose stem contains an input name to that input name.
+/// Names inside longer matching names (diffuse in inner_diffuse) don't count, other double matches are skipped.
+fn match_contained_names(files: HashMap<String, InputFile>) -> HashMap<String, InputFile> {
+    let mut res = HashMap::new();
+    let mut renamed = Vec::new();
+    for (stem, file) in files {
+        // sidecars are matched by the name of their image instead
+        if INPUTS.iter().any(|i| i.name == stem) || file.is_sidecar() {
+            res.insert(stem, file);
+            continue;
+        }
+        let lower = stem.to_lowercase();
+        let found: Vec<&str> = INPUTS.iter().map(|i| i.name).filter(|n| lower.contains(n)).collect();
+        let found: Vec<&str> = found
+            .iter()
+            .filter(|n| !found.iter().any(|m| m != *n && m.contains(*n)))
+            .copied()
+            .collect();
+        match found.as_slice() {
+            [name] => renamed.push((name.to_string(), file)),
+            [] => {
+                res.insert(stem, file);
+            }
+            names => log!(
+                "Warning: {} matches several inputs ({}), the file will be ignored.",
+                file.file_name(),
+                names.join(", ")
+            ),
+        }
+    }
+    // exact names take priority over contained ones
+    renamed.sort_by_key(|(_, file)| file.file_name());
+    for (name, file) in renamed {
+        if let Some(other) = res.get(&name) {
+            log!("Warning: {} and {} both match {}, {} will be ignored.", other.file_name(), file.file_name(), name, file.file_name());
+            continue;
+        }
+        log!("Using {} as {}.", file.file_name(), name);
+        res.insert(name, file);
+    }
+    res
+}
+
+fn get_file_paths<P>(path: P, match_mode: MatchMode) -> std::io::Result<HashMap<String, InputFile>>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let files = get_input_files(path)?;
+    Ok(match match_mode {
+        MatchMode::Exact => files,
+        MatchMode::Contains => match_contained_names(files),
+    })
 }
 
-fn get_file_paths<P>(path: P) -> std::io::Result<HashMap<String, PathBuf>>
+fn get_input_files<P>(path: P) -> std::io::Result<HashMap<String, InputFile>>
 where
     P: AsRef<Path> + std::fmt::Debug,
 {
+    if is_zip(path.as_ref()) {
+        return get_archived_files(path.as_ref());
+    }
+    let ignored = match std::fs::read_to_string(long_path(&path.as_ref().join(IGNORE_FILE))) {
+        Ok(text) => parse_ignore_patterns(&text),
+        Err(_) => vec![],
+    };
     // Get a list of all entries in the folder
-    let entries = std::fs::read_dir(path)?;
+    let entries = std::fs::read_dir(long_path(path.as_ref()))?;
     // Extract the filenames from the directory entries and store them in a vector
-    let file_names: HashMap<String, PathBuf> = entries
+    let file_names: HashMap<String, InputFile> = entries
         .filter_map(|entry| {
             let path = entry.ok()?.path();
-            if path.is_file() {
+            let Some(name) = path.file_name()?.to_str() else {
+                log!("Warning: Skipping {}, its file name is not valid Unicode.", path.display());
+                return None;
+            };
+            if path.is_file() && !is_ignored(&ignored, name) {
                 Some((
                     Path::new(path.file_name()?)
                         .file_stem()?
                         .to_str()?
                         .to_owned(),
-                    path,
+                    InputFile::Disk(path),
                 ))
             } else {
                 None
@@ -58,6 +597,41 @@ where
     Ok(file_names)
 }
 
+/// Reads all files of the archive into memory, folders inside the archive are ignored.
+fn get_archived_files(path: &Path) -> std::io::Result<HashMap<String, InputFile>> {
+    let mut archive = zip::ZipArchive::new(File::open(long_path(path))?)?;
+    let ignored = match archive.by_name(IGNORE_FILE) {
+        Ok(mut entry) => {
+            let mut text = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut text)?;
+            parse_ignore_patterns(&text)
+        }
+        Err(_) => vec![],
+    };
+    let mut file_names = HashMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let entry_path = match entry.enclosed_name() {
+            Some(p) => p.to_path_buf(),
+            None => continue,
+        };
+        let (name, stem) = match (entry_path.file_name(), entry_path.file_stem()) {
+            (Some(n), Some(s)) => (n.to_string_lossy().to_string(), s.to_string_lossy().to_string()),
+            _ => continue,
+        };
+        if is_ignored(&ignored, &name) {
+            continue;
+        }
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        std::io::Read::read_to_end(&mut entry, &mut data)?;
+        file_names.insert(stem, InputFile::Archived { name, data });
+    }
+    Ok(file_names)
+}
+
 fn pick_format(properties: ImageProps, use_old_format: bool, high_quality: bool) -> ImageFormat {
     match use_old_format {
         true => match properties {
@@ -65,6 +639,7 @@ fn pick_format(properties: ImageProps, use_old_format: bool, high_quality: bool)
             ImageProps::RGB => ImageFormat::BC1Unorm,
             ImageProps::RGBFullAlpha => ImageFormat::BC3Unorm,
             ImageProps::RGBCutoutAlpha => ImageFormat::BC1Unorm,
+            ImageProps::TwoChannel => ImageFormat::BC1Unorm,
             ImageProps::Uncompressed => ImageFormat::R8G8B8A8Unorm,
         },
         false => match properties {
@@ -84,203 +659,1147 @@ fn pick_format(properties: ImageProps, use_old_format: bool, high_quality: bool)
                     ImageFormat::BC1Unorm
                 }
             }
+            ImageProps::TwoChannel => ImageFormat::BC5Unorm,
             ImageProps::Uncompressed => ImageFormat::R8G8B8A8Unorm,
         },
     }
 }
 
-fn load_input_image<P>(path: Option<P>) -> Option<DynamicImage>
-where
-    P: AsRef<Path> + std::fmt::Debug,
-{
-    if path.is_none() {
+fn decode_archived(name: &str, data: &[u8]) -> image::ImageResult<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(data));
+    match image::ImageFormat::from_path(name) {
+        Ok(format) => reader.set_format(format),
+        Err(_) => reader = reader.with_guessed_format()?,
+    }
+    reader.decode()
+}
+
+/// Decodes all mipmaps of a dds input, so they can be encoded again with --keep-mips.
+fn load_source_mips(file: &InputFile) -> Option<SurfaceRgba8<Vec<u8>>> {
+    let dds = file.read_dds().ok()?;
+    if dds.get_num_mipmap_levels() <= 1 {
         return None;
     }
-    let path = path.unwrap();
-    let path_readable = path
-        .as_ref()
-        .file_name()
-        .unwrap()
-        .to_string_lossy()
-        .to_string();
-    match ImageReader::open(path) {
-        Ok(reader) => match reader.decode() {
-            Ok(img) => {
-                println!("Found {}, pixel type {:?}.", path_readable, img.color());
-                Some(img)
-            }
-            Err(e) => {
-                println!(
-                    "Error decoding {}, file will be ignored. Details: {}",
-                    path_readable, e
-                );
-                None
-            }
-        },
-        Err(_) => {
-            //println!("Error opening {}: {}", path_readable, e);
+    match SurfaceRgba8::decode_dds(&dds) {
+        Ok(surface) => {
+            log!("Keeping the {} mipmaps of {}.", surface.mipmaps, file.file_name());
+            Some(surface)
+        }
+        Err(e) => {
+            error!("load", Some(file.file_name().as_str()), "Error decoding the mipmaps of {}, they will be generated. Details: {}", file.file_name(), e);
             None
         }
     }
 }
 
-fn create_textures(images: InputImages, args: &Args) -> Vec<(&'static str, Dds)> {
-    let mut textures = Vec::new();
+/// Encoder effort, a higher one is slower but closer to the source.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EncodeQuality {
+    Fast,
+    Normal,
+    #[default]
+    Slow,
+}
 
-    if let Some(tex) = create_diffuse(&images, args) {
-        textures.push(("", tex));
-    }
-    if let Some(tex) = create_normal(&images, args) {
-        textures.push(("_n", tex));
+impl std::str::FromStr for EncodeQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fast" => Ok(EncodeQuality::Fast),
+            "normal" => Ok(EncodeQuality::Normal),
+            "slow" => Ok(EncodeQuality::Slow),
+            _ => Err(format!("unknown quality {}, expected fast, normal or slow", s)),
+        }
     }
-    if let Some(tex) = create_generic(&images.glow, ImageProps::RGB, args) {
-        textures.push(("_g", tex));
+}
+
+impl From<EncodeQuality> for image_dds::Quality {
+    fn from(q: EncodeQuality) -> Self {
+        match q {
+            EncodeQuality::Fast => image_dds::Quality::Fast,
+            EncodeQuality::Normal => image_dds::Quality::Normal,
+            EncodeQuality::Slow => image_dds::Quality::Slow,
+        }
     }
-    if let Some(tex) = create_generic(&images.skin_tint, ImageProps::RGB, args) {
-        textures.push(("_sk", tex));
+}
+
+fn quality_for(format: ImageFormat, args: &ForwardArgs) -> image_dds::Quality {
+    match format {
+        ImageFormat::BC7Unorm | ImageFormat::BC7Srgb => args.quality_bc7.into(),
+        _ => args.quality_other.into(),
     }
-    if let Some(tex) = create_generic(&images.height, ImageProps::Grayscale, args) {
-        textures.push(("_p", tex));
+}
+
+/// The kept mipmaps of the input, unless the texture combines it with other images and they no longer match.
+fn kept_mips<'a>(images: &'a InputImages, name: &str, combined: bool) -> Option<&'a SurfaceRgba8<Vec<u8>>> {
+    let mips = images.source_mips.get(name)?;
+    if combined {
+        log!("Warning: {} is combined with other images, its mipmaps are generated instead of kept.", name);
+        return None;
     }
-    if let Some(tex) = create_generic(&images.cubemap, ImageProps::Grayscale, args) {
-        textures.push(("_e", tex));
+    Some(mips)
+}
+
+/// The signed variant of the BC4 and BC5 formats, for --signed.
+fn signed_format(format: ImageFormat) -> Option<ImageFormat> {
+    match format {
+        ImageFormat::BC4Unorm => Some(ImageFormat::BC4Snorm),
+        ImageFormat::BC5Unorm => Some(ImageFormat::BC5Snorm),
+        _ => None,
     }
-    if args.complex_parallax {
-        if let Some(tex) = create_complex_parallax(&images, args) {
-            textures.push(("_m", tex));
-        }
-    } else if let Some(tex) = create_generic(&images.env_mask, ImageProps::Grayscale, args) {
-        textures.push(("_m", tex));
+}
+
+/// Maps 0..255 to the signed -127..127 (-1..1), stored offset by 128 so the unsigned block encoder keeps the order of the values.
+fn to_signed(data: &mut [u8]) {
+    for v in data {
+        *v = ((*v as u32 * 254 + 127) / 255 + 1) as u8;
     }
-    if let Some(tex) = create_inner(&images, args) {
-        textures.push(("_i", tex));
+}
+
+/// The inverse of [to_signed] for decoded signed textures.
+pub(crate) fn from_signed(data: &mut [u8]) {
+    for v in data {
+        *v = ((v.saturating_sub(1) as u32 * 255 + 127) / 254) as u8;
     }
-    if let Some(tex) = create_generic(&images.subsurface, ImageProps::RGB, args) {
-        textures.push(("_subsurface", tex));
+}
+
+/// Switches the endpoints of every BC4 block (BC5 has two per block) between the offset and the two's complement encoding.
+/// Flipping the top bit keeps the order of the endpoints, so the interpolation mode of the block stays the same.
+pub(crate) fn flip_signed_endpoints(data: &mut [u8]) {
+    for block in data.chunks_exact_mut(8) {
+        block[0] ^= 0x80;
+        block[1] ^= 0x80;
     }
-    if let Some(tex) = create_generic(&images.specular, ImageProps::Grayscale, args) {
-        textures.push(("_s", tex));
+}
+
+/// Every format image_dds can encode, for --dxgi-format.
+const DXGI_FORMATS: [ImageFormat; 21] = [
+    ImageFormat::R8Unorm,
+    ImageFormat::R8G8B8A8Unorm,
+    ImageFormat::R8G8B8A8Srgb,
+    ImageFormat::R16G16B16A16Float,
+    ImageFormat::R32G32B32A32Float,
+    ImageFormat::B8G8R8A8Unorm,
+    ImageFormat::B8G8R8A8Srgb,
+    ImageFormat::BC1Unorm,
+    ImageFormat::BC1Srgb,
+    ImageFormat::BC2Unorm,
+    ImageFormat::BC2Srgb,
+    ImageFormat::BC3Unorm,
+    ImageFormat::BC3Srgb,
+    ImageFormat::BC4Unorm,
+    ImageFormat::BC4Snorm,
+    ImageFormat::BC5Unorm,
+    ImageFormat::BC5Snorm,
+    ImageFormat::BC6Ufloat,
+    ImageFormat::BC6Sfloat,
+    ImageFormat::BC7Unorm,
+    ImageFormat::BC7Srgb,
+];
+
+/// The DXGI name of the format, for example BC7_UNORM_SRGB.
+fn dxgi_name(format: ImageFormat) -> String {
+    format!("{:?}", DxgiFormat::from(format)).to_uppercase()
+}
+
+/// The exact format of a texture, given as normal=BC5_UNORM.
+#[derive(Clone, PartialEq)]
+pub struct DxgiFormatSpec {
+    texture: TextureType,
+    format: ImageFormat,
+}
+
+impl std::str::FromStr for DxgiFormatSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, format) = s.split_once('=').ok_or(format!("invalid dxgi format {}, expected for example normal=BC5_UNORM", s))?;
+        let texture = TextureType::from_name(name.trim()).ok_or(format!(
+            "unknown texture {} in dxgi format, expected one of: {}",
+            name,
+            SLOTS.map(|s| s.name).join(", ")
+        ))?;
+        let wanted = format.trim().to_uppercase();
+        let wanted = wanted.strip_prefix("DXGI_FORMAT_").unwrap_or(&wanted);
+        let format = DXGI_FORMATS.into_iter().find(|f| dxgi_name(*f) == wanted).ok_or(format!(
+            "unsupported dxgi format {}, expected one of: {}",
+            format,
+            DXGI_FORMATS.map(dxgi_name).join(", ")
+        ))?;
+        Ok(DxgiFormatSpec { texture, format })
     }
-    if let Some(tex) = create_generic(&images.backlight, ImageProps::RGB, args) {
-        textures.push(("_b", tex));
+}
+
+/// The arguments for creating one texture: only its own --dxgi-format is kept, so [encode] can apply it without knowing the texture,
+/// and --opaque-alpha only names the texture when it is `opaque`.
+fn texture_args<'a>(args: &'a ForwardArgs, texture: &Output, opaque: bool, narrowed: &'a mut Option<ForwardArgs>) -> &'a ForwardArgs {
+    if args.dxgi_format.is_empty() && args.opaque_alpha.is_empty() {
+        return args;
     }
-    textures
+    let mut own = args.clone();
+    own.dxgi_format.retain(|f| Output::Slot(f.texture) == *texture);
+    own.opaque_alpha = if opaque { vec![texture.name()] } else { Vec::new() };
+    narrowed.insert(own)
 }
 
-fn create_complex_parallax(images: &InputImages, args: &Args) -> Option<Dds> {
-    let (w, h) = {
-        if let Some(img) = &images.env_mask{
-            (img.width(), img.height())
-        }
-        else if let Some(img) = &images.glossiness{
-            (img.width(), img.height())
-        }
-        else if let Some(img) = &images.metallic{
-            (img.width(), img.height())
-        }
-        else if let Some(img) = &images.height{
-            (img.width(), img.height())
-        }
-        else{
-            println!("Error: Complex parallax material selected, but none of the images (R: env_mask, G: glossiness, B: metallic, A: height) available!");
-            return None
-        }
+/// Encodes the texture, reusing the mipmaps of the dds input if there are any.
+/// An explicit --dxgi-format replaces the picked format. When the encoder fails the error is reported,
+/// with --fallback-uncompressed the texture is then written as R8G8B8A8 instead of being skipped.
+/// `cutout` keeps the 1 bit alpha of a cutout texture when it is encoded as BC1.
+pub(crate) fn encode(res: &image::RgbaImage, format: ImageFormat, mips: Option<&SurfaceRgba8<Vec<u8>>>, cutout: bool, args: &ForwardArgs) -> Option<Dds> {
+    let e = match try_encode(res, format, mips, cutout, args) {
+        Ok(tex) => return Some(tex),
+        Err(e) => e,
     };
-    let mut res = image::RgbaImage::new(w, h);
-    for y in 0..res.height() {
-        for x in 0..res.width() {
-            res.put_pixel(x, y, Rgba([0, 5, 0, 0]));
-        }
+    let format = args.dxgi_format.last().map_or(format, |f| f.format);
+    if !args.fallback_uncompressed {
+        error!("create", None, "Error encoding the texture as {:?}, it is skipped! Use --fallback-uncompressed to write it uncompressed. Details: {}", format, e);
+        return None;
     }
-    if let Some(img) = &images.env_mask{
-        for y in 0..img.height() {
-            for x in 0..img.width() {
-                let p = img.get_pixel(x, y);
-                res.get_pixel_mut(x, y).0[0] = p.0[0];
-            }
+    error!("create", None, "Error encoding the texture as {:?}, it is written uncompressed instead. Details: {}", format, e);
+    let mipmaps = mip_count(res.width(), res.height(), args);
+    match dds_from_image(res, ImageFormat::R8G8B8A8Unorm, image_dds::Quality::Fast, mipmaps) {
+        Ok(tex) => Some(tex),
+        Err(e) => {
+            error!("create", None, "Error: Cannot encode the texture uncompressed either, it is skipped! Details: {}", e);
+            None
         }
     }
-    if let Some(img) = &images.glossiness{
-        for y in 0..img.height() {
-            for x in 0..img.width() {
-                let p = img.get_pixel(x, y);
-                res.get_pixel_mut(x, y).0[1] = p.0[0];
+}
+
+fn try_encode(res: &image::RgbaImage, format: ImageFormat, mips: Option<&SurfaceRgba8<Vec<u8>>>, cutout: bool, args: &ForwardArgs) -> Result<Dds, String> {
+    let explicit = args.dxgi_format.last().map(|f| f.format);
+    let format = explicit.unwrap_or(format);
+    let signed = signed_format(format).filter(|_| args.signed && explicit.is_none());
+    let format = signed.unwrap_or(format);
+    let quality = quality_for(format, args);
+    let mipmaps = mip_count(res.width(), res.height(), args);
+    let mut tex = match mips {
+        Some(surface) if (surface.width, surface.height) == res.dimensions() => {
+            let mut data = res.as_raw().clone();
+            data.extend_from_slice(&surface.data[data.len()..]);
+            if signed.is_some() {
+                to_signed(&mut data);
             }
+            let surface = SurfaceRgba8 { data, ..*surface };
+            let surface = match mipmaps {
+                image_dds::Mipmaps::GeneratedExact(count) if count < surface.mipmaps => truncate_mips(&surface, count),
+                _ => surface,
+            };
+            surface
+                .encode_dds(format, quality, image_dds::Mipmaps::FromSurface)
+                .map_err(|e| e.to_string())?
         }
-    }
-    if let Some(img) = &images.metallic{
-        for y in 0..img.height() {
-            for x in 0..img.width() {
-                let p = img.get_pixel(x, y);
-                res.get_pixel_mut(x, y).0[2] = p.0[0];
-            }
+        _ if signed.is_some() => {
+            let mut res = res.clone();
+            to_signed(&mut res);
+            dds_from_image(&res, format, quality, mipmaps).map_err(|e| e.to_string())?
         }
+        _ => dds_from_image(res, format, quality, mipmaps).map_err(|e| e.to_string())?,
+    };
+    if signed.is_some() {
+        flip_signed_endpoints(&mut tex.data);
+    }
+    if cutout && matches!(format, ImageFormat::BC1Unorm | ImageFormat::BC1Srgb) && res.pixels().any(|p| p.0[3] < BC1_ALPHA_THRESHOLD) {
+        // the pixels of every mipmap, downsampled the same way as the encoded ones
+        let rgba = match mips {
+            Some(surface) if (surface.width, surface.height) == res.dimensions() => surface.data[..].to_vec(),
+            _ => dds_from_image(res, ImageFormat::R8G8B8A8Unorm, quality, mipmaps).map_err(|e| e.to_string())?.data,
+        };
+        punch_through_alpha(&mut tex, &rgba);
     }
-    if let Some(img) = &images.height{
-        for y in 0..img.height() {
-            for x in 0..img.width() {
-                let p = img.get_pixel(x, y);
-                res.get_pixel_mut(x, y).0[3] = p.0[0];
+    Ok(tex)
+}
+
+/// BC1 pixels with less alpha than this are transparent.
+const BC1_ALPHA_THRESHOLD: u8 = 128;
+
+fn rgb565(c: u16) -> [i32; 3] {
+    let (r, g, b) = ((c >> 11) & 31, (c >> 5) & 63, c & 31);
+    [((r << 3) | (r >> 2)) as i32, ((g << 2) | (g >> 4)) as i32, ((b << 3) | (b >> 2)) as i32]
+}
+
+/// The BC1 encoder ignores the alpha and only writes four color blocks. BC1 keeps 1 bit alpha in three color blocks,
+/// where the first endpoint is not above the second and index 3 is transparent black. Blocks with transparent pixels
+/// are rewritten that way, their opaque pixels get the nearest of the two endpoints and their midpoint.
+/// `rgba` holds the source pixels of every mipmap of every layer, in the order of the blocks.
+fn punch_through_alpha(tex: &mut Dds, rgba: &[u8]) {
+    let (width, height) = (tex.get_width(), tex.get_height());
+    let mips = tex.get_num_mipmap_levels().max(1);
+    let mut block_at = 0;
+    let mut pixel_at = 0;
+    while pixel_at < rgba.len() && block_at < tex.data.len() {
+        for mip in 0..mips {
+            let (w, h) = ((width >> mip).max(1), (height >> mip).max(1));
+            let level = &rgba[pixel_at..pixel_at + (w * h * 4) as usize];
+            for by in (0..h).step_by(4) {
+                for bx in (0..w).step_by(4) {
+                    let block = &mut tex.data[block_at..block_at + 8];
+                    block_at += 8;
+                    let pixel = |i: u32| {
+                        let (x, y) = (bx + i % 4, by + i / 4);
+                        let at = ((y * w + x) * 4) as usize;
+                        (x < w && y < h).then(|| &level[at..at + 4])
+                    };
+                    if !(0..16).filter_map(pixel).any(|p| p[3] < BC1_ALPHA_THRESHOLD) {
+                        continue;
+                    }
+                    let c0 = u16::from_le_bytes([block[0], block[1]]);
+                    let c1 = u16::from_le_bytes([block[2], block[3]]);
+                    let (low, high) = (c0.min(c1), c0.max(c1));
+                    let (a, b) = (rgb565(low), rgb565(high));
+                    let palette = [a, b, [(a[0] + b[0]) / 2, (a[1] + b[1]) / 2, (a[2] + b[2]) / 2]];
+                    let mut indices = 0u32;
+                    for i in 0..16 {
+                        let index = match pixel(i) {
+                            Some(p) if p[3] >= BC1_ALPHA_THRESHOLD => (0..3)
+                                .min_by_key(|&j| (0..3).map(|c| (palette[j][c] - p[c] as i32).pow(2)).sum::<i32>())
+                                .unwrap(),
+                            _ => 3,
+                        };
+                        indices |= (index as u32) << (i * 2);
+                    }
+                    block[..2].copy_from_slice(&low.to_le_bytes());
+                    block[2..4].copy_from_slice(&high.to_le_bytes());
+                    block[4..].copy_from_slice(&indices.to_le_bytes());
+                }
             }
+            pixel_at += level.len();
         }
     }
-    Some(
-        dds_from_image(
-            &res,
-            pick_format(ImageProps::RGBFullAlpha, args.archaic_format, args.high_quality),
-            image_dds::Quality::Slow,
-            image_dds::Mipmaps::GeneratedAutomatic,
-        )
-        .unwrap(),
-    )
 }
 
-fn create_generic(image: &Option<DynamicImage>, props: ImageProps, args: &Args) -> Option<Dds> {
-    if let Some(img) = image {
-        let mut res = image::RgbaImage::new(img.width(), img.height());
-        if let Err(e) = res.copy_from(img, 0, 0) {
-            println!(
-                "Error: Cannot copy from diffuse image to rgba8 texture! {}",
-                e
-            );
-            println!("The format: {:?}", img.color());
-            return None;
-        }
-        let format = pick_format(props, args.archaic_format, args.high_quality);
-        Some(
-            dds_from_image(
-                &res,
-                format,
-                image_dds::Quality::Slow,
-                image_dds::Mipmaps::GeneratedAutomatic,
-            )
-            .unwrap(),
-        )
-    } else {
-        None
+/// The mipmaps to generate: all of them down to 1x1, or with --min-mip-size only those whose smaller side is at least that size.
+fn mip_count(width: u32, height: u32, args: &ForwardArgs) -> image_dds::Mipmaps {
+    let Some(min) = args.min_mip_size.filter(|&min| min > 1) else {
+        return image_dds::Mipmaps::GeneratedAutomatic;
+    };
+    let mut count = 1;
+    while width.min(height) >> count >= min {
+        count += 1;
     }
+    image_dds::Mipmaps::GeneratedExact(count)
 }
 
-fn create_inner(images: &InputImages, args: &Args) -> Option<Dds> {
-    if let Some(img) = &images.inner_diffuse {
-        let mut res = image::RgbaImage::new(img.width(), img.height());
-        let props = if images.inner_depth.is_some() {
-            ImageProps::RGBFullAlpha
-        } else if img.color().has_alpha() {
-            ImageProps::RGBFullAlpha
-        } else {
-            ImageProps::RGB
-        };
-        if let Err(e) = res.copy_from(img, 0, 0) {
-            println!(
-                "Error: Cannot copy from diffuse image to rgba8 texture! {}",
-                e
-            );
-            println!("The format: {:?}", img.color());
-            return None;
+/// The surface with only its first `count` mipmaps in every layer.
+fn truncate_mips(surface: &SurfaceRgba8<Vec<u8>>, count: u32) -> SurfaceRgba8<Vec<u8>> {
+    let mut data = Vec::new();
+    for layer in 0..surface.layers {
+        for mip in 0..count {
+            data.extend_from_slice(surface.get(layer, 0, mip).unwrap());
         }
-        if let Some(depth) = &images.inner_depth {
-            for y in 0..depth.height() {
+    }
+    SurfaceRgba8 { data, mipmaps: count, ..*surface }
+}
+
+/// Images smaller than one compression block are most likely broken exports.
+const MIN_DIMENSION: u32 = 4;
+
+fn large_enough(img: DynamicImage, name: &str) -> Option<DynamicImage> {
+    if img.width() < MIN_DIMENSION || img.height() < MIN_DIMENSION {
+        log!(
+            "Warning: {} is only {}x{} pixels, images smaller than {}x{} are skipped.",
+            name,
+            img.width(),
+            img.height(),
+            MIN_DIMENSION,
+            MIN_DIMENSION
+        );
+        return None;
+    }
+    Some(img)
+}
+
+fn load_input_image(file: Option<&InputFile>) -> Option<DynamicImage> {
+    let file = file?;
+    let path_readable = file.file_name();
+    if file.is_dds() {
+        let decoded = file
+            .read_dds()
+            .and_then(|dds| image_from_dds(&dds, 0).map_err(|e| e.to_string()));
+        return match decoded {
+            Ok(img) => {
+                log!("Found {}, decoded the full resolution of the dds.", path_readable);
+                large_enough(DynamicImage::ImageRgba8(img), &path_readable)
+            }
+            Err(e) => {
+                error!("load", Some(path_readable.as_str()), "Error decoding {}, file will be ignored. Details: {}", path_readable, e);
+                None
+            }
+        };
+    }
+    let decoded = match file {
+        InputFile::Disk(path) => match ImageReader::open(long_path(path)) {
+            Ok(reader) => reader.decode(),
+            Err(e) => {
+                error!("load", Some(path_readable.as_str()), "Error opening {}, file will be ignored. Details: {}", path.display(), e);
+                return None;
+            }
+        },
+        InputFile::Archived { name, data } => decode_archived(name, data),
+    };
+    match decoded {
+        Ok(img) => {
+            log!("Found {}, pixel type {:?}.", path_readable, img.color());
+            if file.is_indexed_png() {
+                log!("The indexed colors of {} were expanded to {:?}.", path_readable, img.color());
+            }
+            let frames = file.frame_count();
+            if frames > 1 {
+                log!("Warning: {} is animated with {} frames, only the first frame is used.", path_readable, frames);
+            }
+            let mut img = img;
+            sanitize_float(&mut img, &path_readable);
+            large_enough(img, &path_readable)
+        }
+        Err(e) => {
+            error!(
+                "load",
+                Some(path_readable.as_str()),
+                "Error decoding {}, file will be ignored. Details: {}",
+                path_readable, e
+            );
+            None
+        }
+    }
+}
+
+/// Clamps the values of float images to 0..1 and replaces NaN with 0, they would turn into garbage in the 8 bit conversion.
+/// HDR sources are the usual cause.
+fn sanitize_float(img: &mut DynamicImage, name: &str) {
+    let data: &mut [f32] = match img {
+        DynamicImage::ImageRgb32F(i) => i,
+        DynamicImage::ImageRgba32F(i) => i,
+        _ => return,
+    };
+    let (mut nan, mut out_of_range) = (0, 0);
+    for v in data.iter_mut() {
+        if v.is_nan() {
+            nan += 1;
+            *v = 0.0;
+        } else if !(0.0..=1.0).contains(v) {
+            out_of_range += 1;
+            *v = v.clamp(0.0, 1.0);
+        }
+    }
+    if nan > 0 {
+        log!("Warning: {} has {} NaN values, they were set to 0.", name, nan);
+    }
+    if out_of_range > 0 {
+        log!("Warning: {} has {} values outside of 0 to 1 (or infinite), they were clamped.", name, out_of_range);
+    }
+}
+
+/// Durations of the phases of a forward run, printed with --timing.
+#[derive(Default)]
+pub(crate) struct Timings {
+    load: Duration,
+    encode: Vec<(Output, Duration)>,
+    write: Vec<(Output, Duration)>,
+}
+
+impl Timings {
+    fn print(&self) {
+        let total = |list: &[(Output, Duration)]| list.iter().map(|(_, d)| *d).sum::<Duration>();
+        log!("Timing:");
+        log!("  load:   {:>8.2?}", self.load);
+        log!("  encode: {:>8.2?}", total(&self.encode));
+        for (texture, d) in &self.encode {
+            log!("    {:<10} {:>8.2?}", texture.name(), d);
+        }
+        log!("  write:  {:>8.2?}", total(&self.write));
+        log!("  total:  {:>8.2?}", self.load + total(&self.encode) + total(&self.write));
+    }
+}
+
+/// Bytes per 4x4 block of the block compressed formats, or per pixel (false) of the uncompressed ones.
+fn block_bytes(format: DxgiFormat) -> Option<(u32, bool)> {
+    use DxgiFormat::*;
+    match format {
+        BC1_UNorm | BC1_UNorm_sRGB | BC4_UNorm | BC4_SNorm => Some((8, true)),
+        BC2_UNorm | BC2_UNorm_sRGB | BC3_UNorm | BC3_UNorm_sRGB | BC5_UNorm | BC5_SNorm | BC6H_UF16 | BC6H_SF16
+        | BC7_UNorm | BC7_UNorm_sRGB => Some((16, true)),
+        R8G8B8A8_UNorm | R8G8B8A8_UNorm_sRGB => Some((4, false)),
+        _ => None,
+    }
+}
+
+/// The memory the texture takes on the GPU: every mipmap of every layer at the block size of its format.
+fn vram_bytes(tex: &Dds) -> u64 {
+    let Some((bytes, compressed)) = tex.get_dxgi_format().and_then(block_bytes) else {
+        return tex.data.len() as u64;
+    };
+    let (width, height) = (tex.get_width(), tex.get_height());
+    let level_bytes = |level: u32| {
+        let (w, h) = ((width >> level).max(1) as u64, (height >> level).max(1) as u64);
+        if compressed {
+            w.div_ceil(4) * h.div_ceil(4) * bytes as u64
+        } else {
+            w * h * bytes as u64
+        }
+    };
+    let layers = tex.get_num_array_layers().max(1) as u64;
+    (0..tex.get_num_mipmap_levels().max(1)).map(level_bytes).sum::<u64>() * layers
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 20 => format!("{:.1} MiB", b as f64 / (1 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.1} KiB", b as f64 / (1 << 10) as f64),
+        b => format!("{} B", b),
+    }
+}
+
+/// Prints the file and video memory size of each texture and the totals, before anything is written.
+fn print_footprint(textures: &[(Output, Dds)], args: &ForwardArgs) {
+    log!("Footprint:");
+    let (mut disk_total, mut vram_total) = (0, 0);
+    for (texture, tex) in textures {
+        let disk = match args.container {
+            Container::Dds => dds_bytes(tex).len() as u64,
+            Container::Ktx2 => {
+                let mut bytes = Vec::new();
+                write_ktx2(tex, &mut bytes).map_or(tex.data.len() as u64, |_| bytes.len() as u64)
+            }
+        };
+        let vram = vram_bytes(tex);
+        log!("  {:<10} disk {:>10}  vram {:>10}  ({})", texture.name(), format_bytes(disk), format_bytes(vram), describe_texture(tex));
+        disk_total += disk;
+        vram_total += vram;
+    }
+    log!("  {:<10} disk {:>10}  vram {:>10}", "total", format_bytes(disk_total), format_bytes(vram_total));
+}
+
+/// The legacy header format and FourCC of formats that can be described without the DX10 header.
+fn legacy_format(format: DxgiFormat) -> Option<(D3DFormat, Option<u32>)> {
+    match format {
+        DxgiFormat::BC1_UNorm => Some((D3DFormat::DXT1, None)),
+        DxgiFormat::BC2_UNorm => Some((D3DFormat::DXT3, None)),
+        DxgiFormat::BC3_UNorm => Some((D3DFormat::DXT5, None)),
+        // same block sizes as DXT1 and DXT5, only the FourCC differs
+        DxgiFormat::BC4_UNorm => Some((D3DFormat::DXT1, Some(FourCC::ATI1))),
+        DxgiFormat::BC5_UNorm => Some((D3DFormat::DXT5, Some(FourCC::ATI2))),
+        DxgiFormat::R8G8B8A8_UNorm => Some((D3DFormat::A8B8G8R8, None)),
+        _ => None,
+    }
+}
+
+/// Rewrites the texture with the legacy header, unless the DX10 header is requested or required by the format.
+/// Without a preference only the formats that need it (BC6, BC7, sRGB, signed, ...) keep the DX10 header.
+fn choose_header(tex: Dds, dx10_header: Option<bool>) -> Dds {
+    if dx10_header == Some(true) {
+        return tex;
+    }
+    let Some(format) = tex.get_dxgi_format() else {
+        return tex;
+    };
+    let Some((d3d, fourcc)) = legacy_format(format) else {
+        if dx10_header == Some(false) {
+            log!("Warning: {:?} requires the DX10 header, the legacy header cannot be used.", format);
+        }
+        return tex;
+    };
+    let legacy = Dds::new_d3d(NewD3dParams {
+        height: tex.get_height(),
+        width: tex.get_width(),
+        depth: None,
+        format: d3d,
+        mipmap_levels: Some(tex.get_num_mipmap_levels()),
+        caps2: None,
+    });
+    match legacy {
+        Ok(mut legacy) if legacy.data.len() == tex.data.len() => {
+            if let Some(code) = fourcc {
+                legacy.header.spf.fourcc = Some(FourCC(code));
+            }
+            legacy.data = tex.data;
+            legacy
+        }
+        _ => {
+            log!("Warning: Cannot write {:?} with the legacy header, the DX10 header is kept.", format);
+            tex
+        }
+    }
+}
+
+/// The outputs selected with --only and excluded with --skip.
+pub struct OutputFilter {
+    only: Option<Vec<Output>>,
+    skip: Vec<Output>,
+    /// The outputs given to --opaque-alpha.
+    opaque: Vec<Output>,
+}
+
+impl OutputFilter {
+    pub fn new(args: &ForwardArgs, suffixes: &Suffixes) -> Result<OutputFilter, String> {
+        let only = if args.only.is_empty() { None } else { Some(parse_outputs(&args.only, suffixes, &args.pack)?) };
+        let skip = parse_outputs(&args.skip, suffixes, &args.pack)?;
+        if let Some(both) = only.iter().flatten().find(|o| skip.contains(o)) {
+            return Err(format!("{} is given in both --only and --skip", both.name()));
+        }
+        let opaque = parse_outputs(&args.opaque_alpha, suffixes, &args.pack)?;
+        for output in opaque.iter().filter(|o| matches!(o, Output::Slot(TextureType::Normal | TextureType::Inner) | Output::Pack(_))) {
+            log!("Warning: --opaque-alpha does not apply to the {}, its alpha is packed from other inputs.", output.name());
+        }
+        Ok(OutputFilter { only, skip, opaque })
+    }
+
+    fn selects(&self, output: &Output) -> bool {
+        self.only.as_ref().is_none_or(|only| only.contains(output)) && !self.skip.contains(output)
+    }
+}
+
+/// Resolves comma separated texture type names (normal), their suffixes (_n, or as overridden) and pack suffixes.
+fn parse_outputs(list: &[String], suffixes: &Suffixes, packs: &[PackSpec]) -> Result<Vec<Output>, String> {
+    let mut res = Vec::new();
+    for entry in list.iter().flat_map(|l| l.split(',')).map(str::trim).filter(|e| !e.is_empty()) {
+        let output = if let Some(texture) = TextureType::from_name(entry).or_else(|| suffixes.used_by(entry)) {
+            Output::Slot(texture)
+        } else if packs.iter().any(|p| p.suffix == entry) {
+            Output::Pack(entry.to_owned())
+        } else {
+            return Err(format!(
+                "unknown output {}, expected a suffix, a pack suffix or one of: {}",
+                entry,
+                SLOTS.map(|s| s.name).join(", ")
+            ));
+        };
+        res.push(output);
+    }
+    Ok(res)
+}
+
+pub(crate) fn create_textures(images: &InputImages, args: &ForwardArgs, filter: &OutputFilter, timings: &mut Timings) -> Vec<(Output, Dds)> {
+    let mut textures = Vec::new();
+    let mips = |name: &str| images.source_mips.get(name);
+    let mut add = |texture: Output, create: &dyn Fn(&ForwardArgs) -> Option<Dds>| {
+        if !filter.selects(&texture) {
+            return;
+        }
+        let start = Instant::now();
+        let mut narrowed = None;
+        let texture_args = texture_args(args, &texture, filter.opaque.contains(&texture), &mut narrowed);
+        if let Some(tex) = crate::log::grouped(|| create(texture_args)) {
+            // an explicit format already is the color space it names
+            let tex = match texture_args.dxgi_format.is_empty() {
+                true => apply_color_space(tex, &texture, args),
+                false => tex,
+            };
+            if let (Some(game), Some(format)) = (args.target_game, tex.get_dxgi_format()) {
+                if !game.allows(format) {
+                    error!("create", None, "Error: {} does not support {:?}, the {} texture is skipped!", game.name(), format, texture.name());
+                    return;
+                }
+            }
+            // KTX2 has its own header, it is assembled from the dx10 format when writing
+            let tex = match args.container {
+                Container::Dds => choose_header(tex, args.dx10_header),
+                Container::Ktx2 => tex,
+            };
+            timings.encode.push((texture.clone(), start.elapsed()));
+            textures.push((texture, tex));
+        }
+    };
+
+    add(Output::Slot(TextureType::Diffuse), &|args| create_diffuse(images, args));
+    add(Output::Slot(TextureType::Normal), &|args| create_normal(images, args));
+    add(Output::Slot(TextureType::Glow), &|args| create_generic(&images.glow, mips("glow"), ImageProps::RGB, args));
+    // The skin shader reads its subsurface tint from the same slot as skin_tint, both end up in _sk.
+    let (subsurface_tint, subsurface_name) = match (&images.skin_tint, &images.subsurface) {
+        (Some(_), Some(_)) => {
+            log!("Warning: skin_tint and subsurface share the subsurface tint slot, only skin_tint will be used.");
+            (&images.skin_tint, "skin_tint")
+        }
+        (Some(_), None) => (&images.skin_tint, "skin_tint"),
+        (None, _) => (&images.subsurface, "subsurface"),
+    };
+    add(Output::Slot(TextureType::SubsurfaceTint), &|args| create_generic(subsurface_tint, mips(subsurface_name), ImageProps::RGB, args));
+    add(Output::Slot(TextureType::Height), &|args| create_generic(&images.height, mips("height"), ImageProps::Grayscale, args));
+    add(Output::Slot(TextureType::Cubemap), &|args| create_cubemap(images, args));
+    if args.complex_parallax {
+        add(Output::Slot(TextureType::EnvMask), &|args| create_complex_parallax(images, args));
+    } else {
+        add(Output::Slot(TextureType::EnvMask), &|args| create_generic(&images.env_mask, mips("env_mask"), ImageProps::Grayscale, args));
+    }
+    add(Output::Slot(TextureType::Inner), &|args| create_inner(images, args));
+    if args.skin_specular {
+        add(Output::Slot(TextureType::Specular), &|args| create_skin_specular(images, args));
+    } else {
+        add(Output::Slot(TextureType::Specular), &|args| create_generic(&images.specular, mips("specular"), ImageProps::Grayscale, args));
+    }
+    add(Output::Slot(TextureType::Backlight), &|args| create_generic(&images.backlight, mips("backlight"), ImageProps::RGB, args));
+    add(Output::Slot(TextureType::AmbientOcclusion), &|args| create_generic(&images.ao, mips("ao"), ImageProps::Grayscale, args));
+    for pack in &args.pack {
+        add(Output::Pack(pack.suffix.clone()), &|args| create_pack(images, pack, args));
+    }
+    if let Some(suffix) = &args.split_diffuse_alpha {
+        add(Output::Pack(suffix.clone()), &|args| create_diffuse_alpha(images, args));
+    }
+    textures
+}
+
+/// Logs the range of the height channel in one pass, for setting the parallax scale of the material.
+fn log_height_range(height: &DynamicImage, channel: usize) {
+    let (mut min, mut max, mut sum) = (u8::MAX, u8::MIN, 0u64);
+    for (_, _, p) in height.pixels() {
+        let v = p.0[channel];
+        min = min.min(v);
+        max = max.max(v);
+        sum += v as u64;
+    }
+    let mean = sum as f64 / (height.width() as u64 * height.height() as u64).max(1) as f64;
+    log!(
+        "The height ranges from {} to {} with a mean of {:.1} ({:.3} to {:.3}, mean {:.3} normalized).",
+        min,
+        max,
+        mean,
+        min as f64 / 255.0,
+        max as f64 / 255.0,
+        mean / 255.0
+    );
+}
+
+fn create_complex_parallax(images: &InputImages, args: &ForwardArgs) -> Option<Dds> {
+    let source = |name: &'static str, img: &'static str, channel: Channel| {
+        images.get(img).map(|image| PackSource { name, image, channel: resolve_channel(name, image, channel) })
+    };
+    let sources = [
+        source("env_mask", "env_mask", Channel::R),
+        source("glossiness", "glossiness", Channel::R),
+        source("metallic", "metallic", Channel::R),
+        source("height", "height", args.height_channel),
+    ];
+    if let Some(height) = &sources[3] {
+        log_height_range(height.image, height.channel);
+    }
+    if sources.iter().all(|s| s.is_none()) {
+        error!("create", None, "Error: Complex parallax material selected, but none of the images (R: env_mask, G: glossiness, B: metallic, A: height) available!");
+        return None;
+    }
+    let res = match pack_channels(&sources, [0, 5, 0, 0]) {
+        Ok(res) => res,
+        Err(e) => {error!("create", None, "Error: Cannot create the complex parallax texture, {}", e); return None;},
+    };
+    let format = pick_format(ImageProps::RGBFullAlpha, args.archaic_format, args.high_quality);
+    encode(&res, format, None, false, args)
+}
+
+/// The specular map of complex skin: R is the specular intensity, G the glossiness (or the inverted roughness).
+/// A missing one is filled with --specular-value or --glossiness-value.
+fn create_skin_specular(images: &InputImages, args: &ForwardArgs) -> Option<Dds> {
+    let inverted_roughness = match (&images.glossiness, &images.roughness) {
+        (None, Some(roughness)) => {
+            log!("Using the inverted roughness as glossiness of the skin specular.");
+            let mut gloss = roughness.to_luma8();
+            image::imageops::invert(&mut gloss);
+            Some(DynamicImage::ImageLuma8(gloss))
+        }
+        _ => None,
+    };
+    let specular = images
+        .specular
+        .as_ref()
+        .map(|image| PackSource { name: "specular", image, channel: resolve_channel("specular", image, args.specular_channel) });
+    let glossiness = match (&images.glossiness, &inverted_roughness) {
+        (Some(image), _) => Some(PackSource { name: "glossiness", image, channel: 0 }),
+        (None, Some(image)) => Some(PackSource { name: "roughness", image, channel: 0 }),
+        (None, None) => None,
+    };
+    if specular.is_none() && glossiness.is_none() {
+        return None;
+    }
+    let fill = [
+        args.specular_value.unwrap_or(255),
+        args.glossiness_value.unwrap_or(128),
+        0,
+        255,
+    ];
+    let res = match pack_channels(&[specular, glossiness, None, None], fill) {
+        Ok(res) => res,
+        Err(e) => {error!("create", None, "Error: Cannot create the skin specular texture, {}", e); return None;},
+    };
+    let format = pick_format(ImageProps::RGB, args.archaic_format, args.high_quality);
+    encode(&res, format, None, false, args)
+}
+
+/// A channel of an input image that is copied into a channel of a packed texture.
+struct PackSource<'a> {
+    name: &'a str,
+    image: &'a DynamicImage,
+    channel: usize,
+}
+
+/// Builds an image whose channels are copied from channels of the sources, channels without a source are set to `fill`.
+/// The sources must have the same resolution.
+fn pack_channels(sources: &[Option<PackSource>; 4], fill: [u8; 4]) -> Result<image::RgbaImage, String> {
+    let first = sources.iter().flatten().next().ok_or("no source images")?;
+    let (w, h) = first.image.dimensions();
+    if sources.iter().flatten().any(|s| s.image.dimensions() != (w, h)) {
+        let sizes: Vec<String> = sources
+            .iter()
+            .flatten()
+            .map(|s| format!("{} {}x{}", s.name, s.image.width(), s.image.height()))
+            .collect();
+        return Err(format!("the images must have the same resolution, but they are: {}", sizes.join(", ")));
+    }
+    let mut res = image::RgbaImage::from_pixel(w, h, Rgba(fill));
+    for (i, source) in sources.iter().enumerate() {
+        if let Some(source) = source {
+            for (x, y, p) in source.image.pixels() {
+                res.get_pixel_mut(x, y).0[i] = p.0[source.channel];
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// A custom texture packed from channels of the inputs, given as R=metallic.r,G=ao.r,B=height.r,A=glossiness.r:suffix=_rma.
+#[derive(Clone, PartialEq)]
+pub struct PackSpec {
+    /// The input name and channel for each of R, G, B and A.
+    channels: [Option<(&'static str, Channel)>; 4],
+    suffix: String,
+}
+
+impl std::str::FromStr for PackSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mapping, options) = s.split_once(':').ok_or("expected a mapping followed by :suffix=..., for example R=metallic.r,A=height.r:suffix=_rma")?;
+        let suffix = options
+            .strip_prefix("suffix=")
+            .ok_or(format!("unknown pack option {}, expected suffix=...", options))?
+            .trim();
+        if suffix.is_empty() || suffix.contains(['/', '\\']) {
+            return Err(format!("invalid pack suffix {:?}", suffix));
+        }
+        let mut channels = [None; 4];
+        for entry in mapping.split(',') {
+            let (target, source) = entry.split_once('=').ok_or(format!("invalid pack channel {}, expected for example R=metallic.r", entry))?;
+            let index = match target.trim().to_lowercase().as_str() {
+                "r" => 0,
+                "g" => 1,
+                "b" => 2,
+                "a" => 3,
+                t => return Err(format!("unknown pack channel {}, expected R, G, B or A", t)),
+            };
+            let (name, channel) = source.trim().rsplit_once('.').unwrap_or((source.trim(), "r"));
+            let input = INPUTS.iter().find(|i| i.name == name).ok_or(format!(
+                "unknown input {} in pack, expected one of: {}",
+                name,
+                INPUTS.map(|i| i.name).join(", ")
+            ))?;
+            if channels[index].is_some() {
+                return Err(format!("the pack channel {} is given twice", target.trim()));
+            }
+            channels[index] = Some((input.name, channel.parse::<Channel>()?));
+        }
+        Ok(PackSpec { channels, suffix: suffix.to_owned() })
+    }
+}
+
+/// Whether the color values of a texture are stored gamma encoded (sRGB) or as plain data (linear).
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+/// The color space of some textures, given as diffuse=srgb,glow=linear.
+#[derive(Clone, PartialEq)]
+pub struct ColorSpaces(Vec<(TextureType, ColorSpace)>);
+
+impl std::str::FromStr for ColorSpaces {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut res = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, space) = entry.split_once('=').ok_or(format!("invalid color space {}, expected for example diffuse=srgb", entry))?;
+            let texture = TextureType::from_name(name.trim()).ok_or(format!(
+                "unknown texture {} in color space, expected one of: {}",
+                name,
+                SLOTS.map(|s| s.name).join(", ")
+            ))?;
+            let space = match space.trim().to_lowercase().as_str() {
+                "srgb" => ColorSpace::Srgb,
+                "linear" => ColorSpace::Linear,
+                _ => return Err(format!("unknown color space {}, expected srgb or linear", space)),
+            };
+            res.push((texture, space));
+        }
+        Ok(ColorSpaces(res))
+    }
+}
+
+/// The color space given for the texture, the last one wins. Linear without one.
+fn color_space(args: &ForwardArgs, texture: &Output) -> ColorSpace {
+    let Output::Slot(texture) = texture else {
+        return ColorSpace::Linear;
+    };
+    args.colorspace
+        .iter()
+        .flat_map(|c| c.0.iter())
+        .rfind(|(t, _)| t == texture)
+        .map_or(ColorSpace::Linear, |(_, space)| *space)
+}
+
+fn srgb_format(format: DxgiFormat) -> Option<DxgiFormat> {
+    use DxgiFormat::*;
+    match format {
+        BC1_UNorm => Some(BC1_UNorm_sRGB),
+        BC2_UNorm => Some(BC2_UNorm_sRGB),
+        BC3_UNorm => Some(BC3_UNorm_sRGB),
+        BC7_UNorm => Some(BC7_UNorm_sRGB),
+        R8G8B8A8_UNorm => Some(R8G8B8A8_UNorm_sRGB),
+        _ => None,
+    }
+}
+
+/// Marks the texture as sRGB. The blocks are the same, only the format tells the game to decode the gamma.
+/// Formats without an sRGB variant (BC4, BC5) stay linear with a warning.
+fn apply_color_space(mut tex: Dds, texture: &Output, args: &ForwardArgs) -> Dds {
+    if color_space(args, texture) == ColorSpace::Linear {
+        return tex;
+    }
+    let Some(format) = tex.get_dxgi_format() else {
+        return tex;
+    };
+    match (srgb_format(format), tex.header10.as_mut()) {
+        (Some(srgb), Some(header)) => header.dxgi_format = srgb,
+        _ => log!("Warning: {:?} has no sRGB variant, the {} texture stays linear.", format, texture.name()),
+    }
+    tex
+}
+
+/// One channel of an input read from a separate file, given as diffuse.r=red.png.
+#[derive(Clone, PartialEq)]
+pub struct ChannelFile {
+    input: &'static str,
+    channel: usize,
+    path: PathBuf,
+}
+
+impl std::str::FromStr for ChannelFile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, path) = s.split_once('=').ok_or(format!("invalid channel file {}, expected for example diffuse.r=red.png", s))?;
+        let (name, channel) = target.trim().rsplit_once('.').ok_or(format!("invalid channel file {}, the channel is missing, for example diffuse.r", target))?;
+        let input = INPUTS.iter().find(|i| i.name == name).ok_or(format!(
+            "unknown input {} in channel file, expected one of: {}",
+            name,
+            INPUTS.map(|i| i.name).join(", ")
+        ))?;
+        let channel = match channel.parse::<Channel>()? {
+            Channel::R => 0,
+            Channel::G => 1,
+            Channel::B => 2,
+            Channel::A => 3,
+        };
+        Ok(ChannelFile { input: input.name, channel, path: PathBuf::from(path.trim()) })
+    }
+}
+
+/// Assembles the inputs given as --channel-file from the first channel of each file, replacing the images of the input directory.
+/// Channels without a file are black, alpha is opaque.
+fn assemble_channel_files(images: &mut InputImages, files: &[ChannelFile], in_dir: &Path) -> Result<(), String> {
+    let mut inputs: Vec<&'static str> = files.iter().map(|f| f.input).collect();
+    inputs.sort();
+    inputs.dedup();
+    for input in inputs {
+        let mut loaded: [Option<(String, DynamicImage)>; 4] = [None, None, None, None];
+        for file in files.iter().filter(|f| f.input == input) {
+            let path = if file.path.exists() { file.path.clone() } else { in_dir.join(&file.path) };
+            let img = image::open(long_path(&path)).map_err(|e| format!("cannot open the channel file {}: {}", path.display(), e))?;
+            if loaded[file.channel].is_some() {
+                return Err(format!("the channel {} of {} is given twice", "RGBA".as_bytes()[file.channel] as char, input));
+            }
+            log!("Found {}, used as channel {} of {}.", path.display(), "RGBA".as_bytes()[file.channel] as char, input);
+            loaded[file.channel] = Some((path.display().to_string(), img));
+        }
+        let sources = loaded.each_ref().map(|l| l.as_ref().map(|(name, image)| PackSource { name, image, channel: 0 }));
+        let res = pack_channels(&sources, [0, 0, 0, 255])?;
+        let Some(target) = images.get_mut(input) else {
+            continue;
+        };
+        if target.is_some() {
+            log!("Warning: {} is assembled from channel files, the {} image of the input directory is not used.", input, input);
+        }
+        *target = Some(DynamicImage::ImageRgba8(res));
+        images.source_mips.remove(input);
+    }
+    Ok(())
+}
+
+/// Writes the luminance of one input into the alpha of another, given as --mask-to-alpha source=target.
+/// For masks authored as black and white color images.
+fn masks_to_alpha(images: &mut InputImages, entries: &[String]) -> Result<(), String> {
+    let input_name = |name: &str| {
+        INPUTS.iter().find(|i| i.name == name.trim()).map(|i| i.name).ok_or(format!(
+            "unknown input {} in --mask-to-alpha, expected one of: {}",
+            name,
+            INPUTS.map(|i| i.name).join(", ")
+        ))
+    };
+    for entry in entries {
+        let (source, target) = entry
+            .split_once('=')
+            .ok_or(format!("invalid mask {}, expected source=target, for example env_mask=diffuse", entry))?;
+        let (source, target) = (input_name(source)?, input_name(target)?);
+        let Some(mask) = images.get(source) else {
+            log!("Warning: There is no {} image, the alpha of {} is kept.", source, target);
+            continue;
+        };
+        let luma = mask.to_luma8();
+        let Some(Some(img)) = images.get_mut(target) else {
+            log!("Warning: There is no {} image to write the {} mask into.", target, source);
+            continue;
+        };
+        if img.dimensions() != luma.dimensions() {
+            return Err(format!(
+                "the {} mask ({}x{}) has a different resolution than {} ({}x{})",
+                source, luma.width(), luma.height(), target, img.width(), img.height()
+            ));
+        }
+        let mut res = img.to_rgba8();
+        for (p, l) in res.pixels_mut().zip(luma.pixels()) {
+            p.0[3] = l.0[0];
+        }
+        log!("Using the luminance of {} as the alpha of {}.", source, target);
+        *img = DynamicImage::ImageRgba8(res);
+        images.source_mips.remove(target);
+    }
+    Ok(())
+}
+
+/// Warns about inputs that no output reads with the current options, with the option that would use them.
+fn warn_unused_inputs(images: &InputImages, args: &ForwardArgs) {
+    let used_elsewhere = |name: &str| {
+        args.pack.iter().any(|p| p.channels.iter().flatten().any(|(n, _)| *n == name))
+            || args.mask_to_alpha.iter().any(|m| m.split_once('=').is_some_and(|(s, _)| s.trim() == name))
+    };
+    let rules = [
+        ("metallic", args.complex_parallax, "--complex-parallax"),
+        ("glossiness", args.complex_parallax || args.skin_specular, "--complex-parallax or --skin-specular"),
+        ("roughness", args.skin_specular && images.glossiness.is_none(), "--skin-specular without a glossiness image"),
+        ("inner_depth", images.inner_diffuse.is_some(), "an inner_diffuse image"),
+    ];
+    for (name, used, hint) in rules {
+        if images.get(name).is_some() && !used && !used_elsewhere(name) {
+            log!("Warning: The {} image is not used by any texture, it needs {}.", name, hint);
+        }
+    }
+}
+
+fn create_pack(images: &InputImages, pack: &PackSpec, args: &ForwardArgs) -> Option<Dds> {
+    let mut sources: [Option<PackSource>; 4] = [None, None, None, None];
+    for (source, spec) in sources.iter_mut().zip(pack.channels) {
+        if let Some((name, channel)) = spec {
+            let Some(image) = images.get(name) else {
+                error!("create", None, "Error: The pack {} needs the {} image, which is not available!", pack.suffix, name);
+                return None;
+            };
+            *source = Some(PackSource { name, image, channel: resolve_channel(name, image, channel) });
+        }
+    }
+    let res = match pack_channels(&sources, [0, 0, 0, 255]) {
+        Ok(res) => res,
+        Err(e) => {error!("create", None, "Error: Cannot create the pack {}, {}", pack.suffix, e); return None;},
+    };
+    let props = if pack.channels[3].is_some() { ImageProps::RGBFullAlpha } else { ImageProps::RGB };
+    let format = pick_format(props, args.archaic_format, args.high_quality);
+    encode(&res, format, None, false, args)
+}
+
+/// The alpha of the diffuse as a texture of its own, for --split-diffuse-alpha. None when the diffuse is opaque.
+fn create_diffuse_alpha(images: &InputImages, args: &ForwardArgs) -> Option<Dds> {
+    let img = images.diffuse_alpha.as_ref()?;
+    if !img.color().has_alpha() || img.to_rgba8().pixels().all(|p| p.0[3] == u8::MAX) {
+        log!("The diffuse is opaque, no separate alpha texture is written.");
+        return None;
+    }
+    create_generic(&Some(extract_alpha(img)), None, ImageProps::Grayscale, args)
+}
+
+/// `mips` are the mipmaps of a dds input to encode instead of generating them.
+pub fn create_generic(
+    image: &Option<DynamicImage>,
+    mips: Option<&SurfaceRgba8<Vec<u8>>>,
+    props: ImageProps,
+    args: &ForwardArgs,
+) -> Option<Dds> {
+    if let Some(img) = image {
+        let mut res = image::RgbaImage::new(img.width(), img.height());
+        if let Err(e) = res.copy_from(img, 0, 0) {
+            error!(
+                "create",
+                None,
+                "Error: Cannot copy from diffuse image to rgba8 texture! {}",
+                e
+            );
+            log!("The format: {:?}", img.color());
+            return None;
+        }
+        let opaque = !args.opaque_alpha.is_empty();
+        if opaque {
+            log!("Making the alpha of the {} opaque.", args.opaque_alpha[0]);
+            for p in res.pixels_mut() {
+                p.0[3] = u8::MAX;
+            }
+        }
+        let cutout = matches!(props, ImageProps::RGBCutoutAlpha) && !opaque;
+        let format = pick_format(props, args.archaic_format, args.high_quality);
+        encode(&res, format, mips.filter(|_| !opaque), cutout, args)
+    } else {
+        None
+    }
+}
+
+/// The cubemap holds the reflected colors. Grayscale images are written in a color format as well,
+/// single channel formats would show up red in game.
+fn create_cubemap(images: &InputImages, args: &ForwardArgs) -> Option<Dds> {
+    let img = images.cubemap.as_ref()?;
+    let props = match determine_image_props(img)? {
+        ImageProps::Grayscale | ImageProps::TwoChannel => ImageProps::RGB,
+        props => props,
+    };
+    create_generic(&images.cubemap, images.source_mips.get("cubemap"), props, args)
+}
+
+fn create_inner(images: &InputImages, args: &ForwardArgs) -> Option<Dds> {
+    if let Some(img) = &images.inner_diffuse {
+        let mut res = image::RgbaImage::new(img.width(), img.height());
+        let props = if images.inner_depth.is_some() || img.color().has_alpha() {
+            ImageProps::RGBFullAlpha
+        } else {
+            ImageProps::RGB
+        };
+        if let Err(e) = res.copy_from(img, 0, 0) {
+            error!(
+                "create",
+                None,
+                "Error: Cannot copy from diffuse image to rgba8 texture! {}",
+                e
+            );
+            log!("The format: {:?}", img.color());
+            return None;
+        }
+        if let Some(depth) = images.inner_depth.as_ref().filter(|d| d.dimensions() != res.dimensions()) {
+            error!(
+                "create",
+                None,
+                "Error: The inner texture needs the inner_depth ({}x{}) at the resolution of the inner_diffuse ({}x{}), it is skipped!",
+                depth.width(),
+                depth.height(),
+                res.width(),
+                res.height()
+            );
+            return None;
+        }
+        if let Some(depth) = &images.inner_depth {
+            for y in 0..depth.height() {
                 for x in 0..depth.width() {
                     let p = depth.get_pixel(x, y);
                     res.get_pixel_mut(x, y).0[3] = p.0[0]; // set inner_depth.r to result.a
@@ -292,76 +1811,421 @@ fn create_inner(images: &InputImages, args: &Args) -> Option<Dds> {
             args.archaic_format,
             true, /* BC1 does badly with normal maps */
         );
-        Some(
-            dds_from_image(
-                &res,
-                format,
-                image_dds::Quality::Slow,
-                image_dds::Mipmaps::GeneratedAutomatic,
-            )
-            .unwrap(),
-        )
+        let mips = kept_mips(images, "inner_diffuse", images.inner_depth.is_some());
+        encode(&res, format, mips, false, args)
     } else {
         None
     }
 }
 
-fn create_normal(images: &InputImages, args: &Args) -> Option<Dds> {
+/// Tangent space normals point out of the surface, so a mean blue below 128 means the Z channel was exported inverted.
+/// Inverts it with --fix-normal-z, otherwise only warns. Returns whether the image was changed.
+fn check_normal_z(img: &mut image::RgbaImage, fix: bool) -> bool {
+    let pixels = (img.width() as u64 * img.height() as u64).max(1);
+    let mean = img.pixels().map(|p| p.0[2] as u64).sum::<u64>() / pixels;
+    if mean >= 128 {
+        return false;
+    }
+    if fix {
+        log!("The blue channel of the normal map is inverted (mean {}), inverting it back.", mean);
+        for p in img.pixels_mut() {
+            p.0[2] = 255 - p.0[2];
+        }
+    } else {
+        log!("Warning: The blue channel of the normal map has a mean of {}, below 128, the Z of the normals looks inverted. Use --fix-normal-z to invert it.", mean);
+    }
+    fix
+}
+
+/// Rescales every normal vector to unit length. Pixels with a zero vector are left untouched.
+fn normalize_normals(img: &mut image::RgbaImage) {
+    for p in img.pixels_mut() {
+        let v: Vec<f32> = p.0[..3].iter().map(|&c| c as f32 / 127.5 - 1.0).collect();
+        let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        if len == 0.0 {
+            continue;
+        }
+        for (c, v) in p.0[..3].iter_mut().zip(v) {
+            *c = ((v / len + 1.0) * 127.5).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Blends the detail normal map into the normals with the partial derivative blend: the slopes (x/z, y/z) of both normals
+/// are added, the detail's scaled by `strength`. The detail is sampled repeating, `tiling` times per its own size.
+fn blend_detail_normal(img: &mut image::RgbaImage, detail: &image::RgbaImage, strength: f32, tiling: f32) {
+    let unpack = |p: &Rgba<u8>| p.0[..3].iter().map(|&c| c as f32 / 127.5 - 1.0).collect::<Vec<f32>>();
+    let (dw, dh) = detail.dimensions();
+    for (x, y, p) in img.enumerate_pixels_mut() {
+        let dx = (x as f32 * tiling) as u32 % dw;
+        let dy = (y as f32 * tiling) as u32 % dh;
+        let base = unpack(p);
+        let detail = unpack(detail.get_pixel(dx, dy));
+        // keep the slopes finite for normals lying in the surface
+        let (bz, dz) = (base[2].max(1.0 / 127.5), detail[2].max(1.0 / 127.5));
+        let n = [base[0] / bz + strength * detail[0] / dz, base[1] / bz + strength * detail[1] / dz, 1.0];
+        let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        for (c, v) in p.0[..3].iter_mut().zip(n) {
+            *c = ((v / len + 1.0) * 127.5).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Loads the --detail-normal, relative to the input directory unless the path exists as given.
+fn load_detail_normal(path: &Path, in_dir: &Path) -> Result<DynamicImage, String> {
+    let path = if path.exists() { path.to_path_buf() } else { in_dir.join(path) };
+    let img = image::open(long_path(&path)).map_err(|e| format!("cannot open the detail normal {}: {}", path.display(), e))?;
+    log!("Found {}, blended into the normal map as the detail normal.", path.display());
+    Ok(img)
+}
+
+fn create_normal(images: &InputImages, args: &ForwardArgs) -> Option<Dds> {
     if let Some(img) = &images.normal {
         let mut res = image::RgbaImage::new(img.width(), img.height());
-        let props = if images.specular.is_some() {
-            ImageProps::RGBFullAlpha
-        } else if img.color().has_alpha() {
+        if args.normal_bc5 && args.archaic_format {
+            log!("Warning: BC5 is not one of the archaic formats, --normal-bc5 is ignored.");
+        }
+        if args.bc3n && !args.archaic_format {
+            log!("Warning: --bc3n only applies to the archaic formats, it is ignored without --archaic-format.");
+        }
+        let bc5 = args.normal_bc5 && !args.archaic_format;
+        let bc3n = args.bc3n && args.archaic_format;
+        // BC3n needs the alpha for X
+        let flatten = args.flatten_normal_alpha && !bc3n;
+        let props = if bc5 {
+            ImageProps::TwoChannel
+        } else if flatten {
+            ImageProps::RGB
+        } else if bc3n || images.specular.is_some() || img.color().has_alpha() {
             ImageProps::RGBFullAlpha
         } else {
             ImageProps::RGB
         };
         if let Err(e) = res.copy_from(img, 0, 0) {
-            println!(
+            error!(
+                "create",
+                None,
                 "Error: Cannot copy from diffuse image to rgba8 texture! {}",
                 e
             );
-            println!("The format: {:?}", img.color());
+            log!("The format: {:?}", img.color());
             return None;
         }
-        if let Some(spec) = &images.specular {
-            for y in 0..spec.height() {
-                for x in 0..spec.width() {
-                    let p = spec.get_pixel(x, y);
-                    res.get_pixel_mut(x, y).0[3] = p.0[0]; // set specular.r to result.a
+        let fixed_z = check_normal_z(&mut res, args.fix_normal_z);
+        if let Some(detail) = &images.detail_normal {
+            blend_detail_normal(&mut res, &detail.to_rgba8(), args.detail_strength, args.detail_tiling);
+        }
+        if args.normalize_normal {
+            normalize_normals(&mut res);
+        }
+        match &images.specular {
+            Some(_) if bc5 => {
+                log!("Warning: BC5 normal maps have no alpha channel, the specular is only written to the separate _s texture.");
+            }
+            Some(_) if bc3n => {
+                log!("Warning: BC3n normal maps store X in the alpha channel, the specular is only written to the separate _s texture.");
+            }
+            Some(_) if flatten => {
+                log!("The specular is not packed into the normal map, its alpha is opaque.");
+            }
+            Some(spec) if spec.dimensions() != res.dimensions() => {
+                error!(
+                    "create",
+                    None,
+                    "Error: The normal map alpha needs the specular ({}x{}) at the resolution of the normal ({}x{}), the specular is only written to its own texture!",
+                    spec.width(),
+                    spec.height(),
+                    res.width(),
+                    res.height()
+                );
+            }
+            Some(spec) if args.specular_luma => {
+                // the Rec. 709 luminance keeps the intensity of colored specular maps
+                for (x, y, p) in spec.to_rgb8().enumerate_pixels() {
+                    let [r, g, b] = p.0.map(|c| c as f32);
+                    res.get_pixel_mut(x, y).0[3] = (0.2126 * r + 0.7152 * g + 0.0722 * b).round().min(255.0) as u8;
                 }
             }
+            Some(spec) => {
+                let c = resolve_channel("specular", spec, args.specular_channel);
+                for y in 0..spec.height() {
+                    for x in 0..spec.width() {
+                        let p = spec.get_pixel(x, y);
+                        res.get_pixel_mut(x, y).0[3] = p.0[c]; // set the specular channel to result.a
+                    }
+                }
+            }
+            None => {}
         }
         let format = pick_format(
             props,
             args.archaic_format,
             true, /* BC1 does badly with normal maps */
         );
-        Some(
-            dds_from_image(
-                &res,
-                format,
-                image_dds::Quality::Slow,
-                image_dds::Mipmaps::GeneratedAutomatic,
-            )
-            .unwrap(),
-        )
+        if flatten {
+            for p in res.pixels_mut() {
+                p.0[3] = u8::MAX;
+            }
+        }
+        if bc3n {
+            // the classic swizzle: X in alpha, Y stays in green, the other channels are empty
+            for p in res.pixels_mut() {
+                p.0 = [0, p.0[1], 0, p.0[0]];
+            }
+        }
+        // the kept mipmaps are the unchanged source, so any change to the top level needs generated ones
+        let packed = args.normalize_normal || bc3n || fixed_z || flatten || images.detail_normal.is_some() || (images.specular.is_some() && !bc5);
+        let mips = kept_mips(images, "normal", packed);
+        encode(&res, format, mips, false, args)
     } else {
         None
     }
 }
 
+/// The color of padded borders, given as RRGGBB or RRGGBBAA hex.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PadColor(pub [u8; 4]);
+
+impl std::str::FromStr for PadColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid color {}, expected RRGGBB or RRGGBBAA hex", s));
+        }
+        let mut color = [255; 4];
+        for (i, c) in color.iter_mut().enumerate().take(hex.len() / 2) {
+            *c = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        Ok(PadColor(color))
+    }
+}
+
+fn pad_buffer<P: image::Pixel>(img: &image::ImageBuffer<P, Vec<P::Subpixel>>, width: u32, height: u32, fill: Option<P>) -> image::ImageBuffer<P, Vec<P::Subpixel>> {
+    let (w, h) = img.dimensions();
+    let (left, top) = ((width - w) / 2, (height - h) / 2);
+    image::ImageBuffer::from_fn(width, height, |x, y| match fill {
+        Some(fill) if x < left || y < top || x >= left + w || y >= top + h => fill,
+        _ => *img.get_pixel(x.saturating_sub(left).min(w - 1), y.saturating_sub(top).min(h - 1)),
+    })
+}
+
+/// Centers the image in a larger one. The border is filled with the color, or with the repeated edge pixels
+/// if there is none, which keeps the edge colors from bleeding into the mipmaps.
+fn pad_image(img: &DynamicImage, width: u32, height: u32, color: Option<PadColor>) -> DynamicImage {
+    let c = color.map(|c| c.0);
+    match img {
+        DynamicImage::ImageLuma8(i) => DynamicImage::ImageLuma8(pad_buffer(i, width, height, c.map(|c| image::Luma([c[0]])))),
+        DynamicImage::ImageLumaA8(i) => DynamicImage::ImageLumaA8(pad_buffer(i, width, height, c.map(|c| image::LumaA([c[0], c[3]])))),
+        DynamicImage::ImageRgb8(i) => DynamicImage::ImageRgb8(pad_buffer(i, width, height, c.map(|c| image::Rgb([c[0], c[1], c[2]])))),
+        other => DynamicImage::ImageRgba8(pad_buffer(&other.to_rgba8(), width, height, c.map(Rgba))),
+    }
+}
+
+/// Center crops or pads every off-square input to a square, before anything is packed so the channels stay aligned.
+fn make_square(images: &mut InputImages, mode: SquareMode, pad_color: Option<PadColor>) {
+    for input in INPUTS.iter() {
+        let Some(Some(img)) = images.get_mut(input.name) else {
+            continue;
+        };
+        let (w, h) = img.dimensions();
+        if w == h {
+            continue;
+        }
+        *img = match mode {
+            SquareMode::Crop => {
+                let size = w.min(h);
+                log!("Warning: {} is {}x{}, cropped {} pixels to {}x{}.", input.name, w, h, w.max(h) - size, size, size);
+                img.crop_imm((w - size) / 2, (h - size) / 2, size, size)
+            }
+            SquareMode::Pad => {
+                let size = w.max(h);
+                log!("Warning: {} is {}x{}, padded {} pixels to {}x{}.", input.name, w, h, size - w.min(h), size, size);
+                pad_image(img, size, size, pad_color)
+            }
+        };
+        // the mipmaps of the dds no longer fit
+        images.source_mips.remove(input.name);
+    }
+}
+
+/// The size given to --force-resolution as WxH.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::str::FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid resolution {}, expected width x height like 2048x2048", s);
+        let lower = s.to_lowercase();
+        let (width, height) = lower.split_once('x').ok_or_else(invalid)?;
+        match (width.trim().parse::<u32>(), height.trim().parse::<u32>()) {
+            (Ok(width), Ok(height)) if width >= MIN_DIMENSION && height >= MIN_DIMENSION => Ok(Resolution { width, height }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Resizes every input to the resolution with a Lanczos filter, before anything is packed so the channels line up.
+fn force_resolution(images: &mut InputImages, size: Resolution) {
+    for input in INPUTS.iter() {
+        let Some(Some(img)) = images.get_mut(input.name) else {
+            continue;
+        };
+        let (w, h) = img.dimensions();
+        if (w, h) == (size.width, size.height) {
+            continue;
+        }
+        if size.width > w || size.height > h {
+            log!("Warning: {} is {}x{}, upscaling it to {}x{} adds no detail and blurs it.", input.name, w, h, size.width, size.height);
+        } else {
+            log!("Resizing {} from {}x{} to {}x{}.", input.name, w, h, size.width, size.height);
+        }
+        *img = img.resize_exact(size.width, size.height, image::imageops::FilterType::Lanczos3);
+        // the mipmaps of the dds no longer fit
+        images.source_mips.remove(input.name);
+    }
+}
+
+fn solid_image(size: (u32, u32), value: u8) -> DynamicImage {
+    DynamicImage::ImageLuma8(image::GrayImage::from_pixel(size.0, size.1, image::Luma([value])))
+}
+
+fn dimensions_of(candidates: &[&Option<DynamicImage>]) -> Option<(u32, u32)> {
+    candidates
+        .iter()
+        .find_map(|img| img.as_ref().map(|img| (img.width(), img.height())))
+}
+
+/// Synthesizes solid images for missing inputs that have a fallback value, at the resolution
+/// of the texture they get packed into.
+fn fill_solid_inputs(images: &mut InputImages, args: &ForwardArgs) {
+    if args.default_normal && images.normal.is_none() {
+        if let Some(diffuse) = &images.diffuse_alpha {
+            log!("No normal image, generated a flat placeholder normal map at the diffuse resolution.");
+            images.normal = Some(DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                diffuse.width(),
+                diffuse.height(),
+                image::Rgb([128, 128, 255]),
+            )));
+        }
+    }
+    if let (None, Some(value)) = (&images.specular, args.specular_value) {
+        // Specular goes into the alpha of the normal map.
+        match dimensions_of(&[&images.normal, &images.diffuse_alpha]) {
+            Some(size) => {
+                log!("No specular image, using the solid value {}.", value);
+                images.specular = Some(solid_image(size, value));
+            }
+            None => error!("create", None, "Error: Specular value set, but there is no normal or diffuse image to take the resolution from!"),
+        }
+    }
+    // Metallic and glossiness go into the complex parallax environment mask.
+    let parallax_size = dimensions_of(&[
+        &images.env_mask,
+        &images.glossiness,
+        &images.metallic,
+        &images.height,
+        &images.diffuse_alpha,
+    ]);
+    for (name, img, value) in [
+        ("metallic", &mut images.metallic, args.metallic_value),
+        ("glossiness", &mut images.glossiness, args.glossiness_value),
+    ] {
+        if let (None, Some(value)) = (&img, value) {
+            match parallax_size {
+                Some(size) => {
+                    log!("No {} image, using the solid value {}.", name, value);
+                    *img = Some(solid_image(size, value));
+                }
+                None => error!("create", None, "Error: {} value set, but there is no image to take the resolution from!", name),
+            }
+        }
+    }
+}
+
+fn gray_alpha_props(img: &DynamicImage) -> ImageProps {
+    let alpha: Vec<u8> = img.to_luma_alpha8().pixels().map(|p| p.0[1]).collect();
+    if alpha.iter().all(|a| *a == u8::MAX) {
+        ImageProps::Grayscale
+    } else if alpha.iter().all(|a| *a == u8::MIN || *a == u8::MAX) {
+        ImageProps::RGBCutoutAlpha
+    } else {
+        ImageProps::RGBFullAlpha
+    }
+}
+
+fn extract_alpha(img: &DynamicImage) -> DynamicImage {
+    let la = img.to_luma_alpha8();
+    let mut a = image::GrayImage::new(la.width(), la.height());
+    for (x, y, p) in la.enumerate_pixels() {
+        a.put_pixel(x, y, image::Luma([p.0[1]]));
+    }
+    DynamicImage::ImageLuma8(a)
+}
+
+fn is_gray_alpha(img: &DynamicImage) -> bool {
+    matches!(img.color(), image::ColorType::La8 | image::ColorType::La16)
+}
+
+/// The single channel slots only read the gray channel, so the alpha of a grayscale+alpha image would vanish silently.
+fn route_gray_alpha(images: &mut InputImages, args: &ForwardArgs) {
+    let mut spec_alpha_used = false;
+    if args.specular_alpha_gloss {
+        match (&images.specular, &images.glossiness) {
+            (Some(spec), None) if is_gray_alpha(spec) => {
+                log!("Using the alpha channel of specular as glossiness.");
+                images.glossiness = Some(extract_alpha(spec));
+                spec_alpha_used = true;
+            }
+            (Some(spec), Some(_)) if is_gray_alpha(spec) => {
+                log!("Glossiness image found, the alpha channel of specular will not be used as glossiness.");
+            }
+            _ => {}
+        }
+    }
+    let single_channel = [
+        ("specular", &images.specular),
+        ("height", &images.height),
+        ("env_mask", &images.env_mask),
+        ("inner_depth", &images.inner_depth),
+        ("metallic", &images.metallic),
+        ("glossiness", &images.glossiness),
+        ("roughness", &images.roughness),
+        ("ao", &images.ao),
+    ];
+    for (name, img) in single_channel {
+        match img {
+            Some(img) if is_gray_alpha(img) && !(name == "specular" && spec_alpha_used) => {
+                log!(
+                    "Warning: {} is grayscale+alpha, but only the gray channel is used. The alpha channel will be discarded.",
+                    name
+                );
+                if name == "specular" && !args.specular_alpha_gloss {
+                    log!("Use --specular-alpha-gloss to use it as glossiness.");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 fn determine_image_props(img: &DynamicImage) -> Option<ImageProps> {
     match img.color() {
         image::ColorType::L8 => Some(ImageProps::Grayscale),
-        image::ColorType::La8 => Some(ImageProps::Grayscale),
+        image::ColorType::La8 => Some(gray_alpha_props(img)),
         image::ColorType::Rgb8 => Some(ImageProps::RGB),
         image::ColorType::Rgba8 => Some(
             if img
                 .as_rgba8()
                 .unwrap()
-                .iter()
-                .all(|p| *p == u8::MIN || *p == u8::MAX)
+                .pixels()
+                .all(|p| p.0[3] == u8::MIN || p.0[3] == u8::MAX)
             {
                 ImageProps::RGBCutoutAlpha
             } else {
@@ -369,14 +2233,14 @@ fn determine_image_props(img: &DynamicImage) -> Option<ImageProps> {
             },
         ),
         image::ColorType::L16 => Some(ImageProps::Grayscale),
-        image::ColorType::La16 => Some(ImageProps::Grayscale),
+        image::ColorType::La16 => Some(gray_alpha_props(img)),
         image::ColorType::Rgb16 => Some(ImageProps::RGB),
         image::ColorType::Rgba16 => Some(
             if img
                 .as_rgba16()
                 .unwrap()
-                .iter()
-                .all(|p| *p == u16::MIN || *p == u16::MAX)
+                .pixels()
+                .all(|p| p.0[3] == u16::MIN || p.0[3] == u16::MAX)
             {
                 ImageProps::RGBCutoutAlpha
             } else {
@@ -386,62 +2250,890 @@ fn determine_image_props(img: &DynamicImage) -> Option<ImageProps> {
         image::ColorType::Rgb32F => Some(ImageProps::RGB),
         image::ColorType::Rgba32F => Some(ImageProps::RGBFullAlpha),
         _ => {
-            println!("Unsupported pixel format {:?}! Skipping...", img.color());
-            None
+            log!("Expanding the pixel format {:?} to Rgba8.", img.color());
+            determine_image_props(&DynamicImage::ImageRgba8(img.to_rgba8()))
         }
     }
 }
 
-fn create_diffuse(images: &InputImages, args: &Args) -> Option<Dds> {
+/// How strongly a pixel below the average height of its neighborhood gets darkened.
+const AO_STRENGTH: f32 = 2.0;
+
+/// A cheap approximation of ambient occlusion: pixels lower than the average of
+/// the surrounding (2 * radius + 1)^2 box are occluded. Edges are clamped.
+fn generate_ao(height: &DynamicImage, radius: u32) -> DynamicImage {
+    let height = height.to_luma8();
+    let (w, h) = height.dimensions();
+    // Summed area table with a zero border, so any box sum takes four lookups.
+    let stride = (w + 1) as usize;
+    let mut sums = vec![0u64; stride * (h + 1) as usize];
+    for y in 0..h as usize {
+        let mut row = 0u64;
+        for x in 0..w as usize {
+            row += height.get_pixel(x as u32, y as u32).0[0] as u64;
+            sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+        }
+    }
+    let mut ao = image::GrayImage::new(w, h);
+    for y in 0..h {
+        for x in 0..w {
+            let x0 = x.saturating_sub(radius) as usize;
+            let y0 = y.saturating_sub(radius) as usize;
+            let x1 = (x + radius + 1).min(w) as usize;
+            let y1 = (y + radius + 1).min(h) as usize;
+            let sum = sums[y1 * stride + x1] + sums[y0 * stride + x0]
+                - sums[y0 * stride + x1]
+                - sums[y1 * stride + x0];
+            let avg = sum as f32 / ((x1 - x0) * (y1 - y0)) as f32;
+            let depth = (avg - height.get_pixel(x, y).0[0] as f32).max(0.0);
+            let value = 255.0 - (depth * AO_STRENGTH).min(255.0);
+            ao.put_pixel(x, y, image::Luma([value as u8]));
+        }
+    }
+    DynamicImage::ImageLuma8(ao)
+}
+
+const DILATE_ITERATIONS: u32 = 8;
+
+/// Fills the RGB of fully transparent pixels with the average of their already filled neighbors,
+/// growing the opaque color outwards by one pixel per iteration. Alpha stays untouched.
+fn dilate_rgb(img: &mut image::RgbaImage, iterations: u32) {
+    let (w, h) = img.dimensions();
+    let mut filled: Vec<bool> = img.pixels().map(|p| p.0[3] != 0).collect();
+    for _ in 0..iterations {
+        let mut next = filled.clone();
+        let mut changed = false;
+        for y in 0..h {
+            for x in 0..w {
+                if filled[(y * w + x) as usize] {
+                    continue;
+                }
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= w as i64 || ny >= h as i64 {
+                        continue;
+                    }
+                    if filled[(ny as u32 * w + nx as u32) as usize] {
+                        let p = img.get_pixel(nx as u32, ny as u32);
+                        for (s, v) in sum.iter_mut().zip(p.0) {
+                            *s += v as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                if count == 0 {
+                    continue;
+                }
+                let p = img.get_pixel_mut(x, y);
+                for (v, s) in p.0.iter_mut().zip(sum) {
+                    *v = (s / count) as u8;
+                }
+                next[(y * w + x) as usize] = true;
+                changed = true;
+            }
+        }
+        filled = next;
+        if !changed {
+            break;
+        }
+    }
+}
+
+pub fn create_diffuse(images: &InputImages, args: &ForwardArgs) -> Option<Dds> {
     if let Some(img) = &images.diffuse_alpha {
         let mut res = image::RgbaImage::new(img.width(), img.height());
         let mut props = determine_image_props(img)?;
         if let Err(e) = res.copy_from(img, 0, 0) {
-            println!(
+            error!(
+                "create",
+                None,
                 "Error: Cannot copy from diffuse image to rgba8 texture! {}",
                 e
             );
-            println!("The format: {:?}", img.color());
+            log!("The format: {:?}", img.color());
             return None;
         }
-        if args.terrain_parallax {
-            if let Some(height) = &images.height {
+        let opaque = !args.opaque_alpha.is_empty();
+        if (args.drop_diffuse_alpha || opaque) && matches!(props, ImageProps::RGBCutoutAlpha | ImageProps::RGBFullAlpha) {
+            log!("Dropping the alpha of the diffuse, it is encoded as opaque.");
+            props = ImageProps::RGB;
+            for p in res.pixels_mut() {
+                p.0[3] = u8::MAX;
+            }
+        }
+        let has_alpha = matches!(props, ImageProps::RGBCutoutAlpha | ImageProps::RGBFullAlpha);
+        let (unpremultiply, premultiply) = match (args.unpremultiply, args.premultiply) {
+            (true, true) => {
+                log!("Warning: --unpremultiply and --premultiply cancel each other, the diffuse colors are kept.");
+                (false, false)
+            }
+            (u, p) => (u && has_alpha, p && has_alpha),
+        };
+        if unpremultiply {
+            // c / a in normalized space, fully transparent pixels have no color to recover
+            for p in res.pixels_mut() {
+                let a = p.0[3] as u32;
+                for c in &mut p.0[..3] {
+                    if let Some(v) = (*c as u32 * 255 + a / 2).checked_div(a) {
+                        *c = v.min(255) as u8;
+                    }
+                }
+            }
+        }
+        if args.dilate_alpha && has_alpha {
+            dilate_rgb(&mut res, DILATE_ITERATIONS);
+        }
+        if premultiply {
+            for p in res.pixels_mut() {
+                let a = p.0[3] as u32;
+                for c in &mut p.0[..3] {
+                    *c = ((*c as u32 * a + 127) / 255) as u8;
+                }
+            }
+        }
+        if args.terrain_parallax && opaque {
+            log!("Warning: --opaque-alpha includes the diffuse, the terrain parallax height is not written into its alpha.");
+        } else if args.terrain_parallax {
+            if let Some(height) = images.height.as_ref().filter(|h| h.dimensions() != res.dimensions()) {
+                error!(
+                    "create",
+                    None,
+                    "Error: Terrain parallax needs the height ({}x{}) at the resolution of the diffuse ({}x{}), the diffuse keeps its alpha!",
+                    height.width(),
+                    height.height(),
+                    res.width(),
+                    res.height()
+                );
+            } else if let Some(height) = &images.height {
                 props = ImageProps::RGBFullAlpha;
+                let c = resolve_channel("height", height, args.height_channel);
+                log_height_range(height, c);
                 for y in 0..height.height() {
                     for x in 0..height.width() {
                         let p = height.get_pixel(x, y);
-                        res.get_pixel_mut(x, y).0[3] = p.0[0]; // set height.r to result.a
+                        res.get_pixel_mut(x, y).0[3] = p.0[c]; // set the height channel to result.a
                     }
                 }
             } else {
-                println!("Error: Terrain parallax selected, but no height image supplied!");
+                error!("create", None, "Error: Terrain parallax selected, but no height image supplied!");
             }
         }
+        let has_alpha = matches!(props, ImageProps::RGBCutoutAlpha | ImageProps::RGBFullAlpha);
+        let cutout = matches!(props, ImageProps::RGBCutoutAlpha);
         let format = pick_format(props, args.archaic_format, args.high_quality);
-        Some(
-            dds_from_image(
-                &res,
-                format,
-                image_dds::Quality::Slow,
-                image_dds::Mipmaps::GeneratedAutomatic,
-            )
-            .unwrap(),
-        )
+        let modified = args.dilate_alpha || args.drop_diffuse_alpha || opaque || unpremultiply || premultiply || (args.terrain_parallax && images.height.is_some());
+        let mips = kept_mips(images, "diffuse", modified);
+        // the BC7 encoder has a single quality for all channels, so the alpha raises the quality of the whole texture
+        match args.alpha_quality.filter(|q| has_alpha && *q > args.quality_bc7) {
+            Some(quality) => {
+                if matches!(format, ImageFormat::BC7Unorm | ImageFormat::BC7Srgb) {
+                    log!("The diffuse has alpha, it is encoded at the --alpha-quality.");
+                }
+                let args = ForwardArgs { quality_bc7: quality, ..args.clone() };
+                encode(&res, format, mips, cutout, &args)
+            }
+            None => encode(&res, format, mips, cutout, args),
+        }
     } else {
         None
     }
 }
 
-pub fn run_forward(args: &Args, in_dir: &PathBuf, out_dir: &PathBuf){
-    let fnames = match get_file_paths(in_dir.as_path()){
-        Ok(fnames) => fnames,
-        Err(e) => {println!("Critical error, cannot get file paths: {}", e); return;},
+/// The output directories of the texture types given with --type-dir name=dir, relative to the output directory unless absolute.
+fn type_dirs(args: &ForwardArgs, out_dir: &Path) -> Result<HashMap<TextureType, PathBuf>, String> {
+    let mut dirs = HashMap::new();
+    for entry in &args.type_dir {
+        let (name, dir) = entry
+            .split_once('=')
+            .ok_or(format!("invalid type dir {}, expected name=dir, for example normal=normals", entry))?;
+        let texture = TextureType::from_name(name.trim()).ok_or(format!(
+            "unknown texture {} in type dir, expected one of: {}",
+            name,
+            SLOTS.map(|s| s.name).join(", ")
+        ))?;
+        let dir = out_dir.join(dir.trim());
+        std::fs::create_dir_all(long_path(&dir)).map_err(|e| format!("cannot create the {} directory {}: {}", name.trim(), dir.display(), e))?;
+        dirs.insert(texture, dir);
+    }
+    Ok(dirs)
+}
+
+/// Archives expect the textures under Textures/ in the same layout as the material paths of the meshes.
+fn game_path_dir(args: &ForwardArgs, out_dir: &Path) -> Option<PathBuf> {
+    let game_path = match &args.game_path {
+        Some(p) => p,
+        None => return Some(out_dir.to_path_buf()),
+    };
+    if game_path.is_absolute()
+        || game_path
+            .components()
+            .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        error!(
+            "setup",
+            None,
+            "Critical error, the game path {} must be a plain relative path, such as armor/iron.",
+            game_path.display()
+        );
+        return None;
+    }
+    let dir = out_dir.join("Textures").join(game_path);
+    if let Err(e) = std::fs::create_dir_all(long_path(&dir)) {
+        error!("setup", None, "Critical error, cannot create the game path directory {}: {}", dir.display(), e);
+        return None;
+    }
+    Some(dir)
+}
+
+/// Checks the inputs with --dry-run: lists them with their resolutions read from the file headers and reports the inputs
+/// that are combined into one texture at different resolutions. Nothing is decoded or written.
+fn preflight(fnames: &HashMap<String, InputFile>, args: &ForwardArgs) -> bool {
+    let mut sizes: HashMap<String, (u32, u32)> = HashMap::new();
+    let mut stems: Vec<&String> = fnames.keys().filter(|stem| INPUTS.iter().any(|i| stem.starts_with(i.name))).collect();
+    stems.sort();
+    let mut success = true;
+    for stem in stems {
+        let file = &fnames[stem];
+        match file.dimensions() {
+            Ok((width, height)) => {
+                log!("Found {}, {}x{}.", file.file_name(), width, height);
+                sizes.insert(stem.clone(), (width, height));
+            }
+            Err(e) => {
+                error!("load", Some(file.file_name().as_str()), "Error reading the size of {}: {}", file.file_name(), e);
+                success = false;
+            }
+        }
+    }
+    let glows: Vec<String> = std::iter::once(String::from("glow"))
+        .chain((2..).map(|i| format!("glow{}", i)).take_while(|n| fnames.contains_key(n)))
+        .collect();
+    // the inputs combined into one texture need the same resolution
+    let mut groups: Vec<Vec<&str>> = vec![vec!["normal", "specular"], vec!["inner_diffuse", "inner_depth"], glows.iter().map(String::as_str).collect()];
+    if args.terrain_parallax {
+        groups.push(vec!["diffuse", "height"]);
+    }
+    if args.complex_parallax {
+        groups.push(vec!["env_mask", "glossiness", "metallic", "height"]);
+    }
+    if args.skin_specular {
+        groups.push(vec!["specular", "glossiness", "roughness"]);
+    }
+    // the forced resolution evens the sizes out
+    for group in groups.into_iter().filter(|_| args.force_resolution.is_none()) {
+        let found: Vec<(&str, (u32, u32))> = group.iter().filter_map(|n| sizes.get(*n).map(|s| (*n, *s))).collect();
+        if found.iter().any(|(_, size)| *size != found[0].1) {
+            let list: Vec<String> = found.iter().map(|(n, (w, h))| format!("{} {}x{}", n, w, h)).collect();
+            error!("load", None, "Error: These inputs are combined into one texture but differ in resolution: {}", list.join(", "));
+            success = false;
+        }
+    }
+    log!("Dry run, nothing was converted.");
+    success
+}
+
+/// Loads the image `name` followed by `name2`, `name3`, ... until the first missing number.
+fn load_numbered_images(fnames: &HashMap<String, InputFile>, name: &str) -> Vec<DynamicImage> {
+    let mut res: Vec<DynamicImage> = load_input_image(fnames.get(name)).into_iter().collect();
+    for i in 2.. {
+        match fnames.get(&format!("{}{}", name, i)) {
+            Some(file) => res.extend(load_input_image(Some(file))),
+            None => break,
+        }
+    }
+    res
+}
+
+/// Sums the images channel by channel, clamped to 255. Images with a different resolution than the first one are skipped.
+fn combine_additive(name: &str, images: Vec<DynamicImage>) -> Option<DynamicImage> {
+    let mut iter = images.into_iter();
+    let first = iter.next()?;
+    let mut res = first.to_rgba8();
+    let mut combined = false;
+    for img in iter {
+        if img.dimensions() != res.dimensions() {
+            error!(
+                "load",
+                None,
+                "Error: The {} images have different resolutions ({:?} and {:?}), the extra one is skipped!",
+                name,
+                res.dimensions(),
+                img.dimensions()
+            );
+            continue;
+        }
+        for (p, q) in res.pixels_mut().zip(img.to_rgba8().pixels()) {
+            for (c, d) in p.0[..3].iter_mut().zip(q.0) {
+                *c = c.saturating_add(d);
+            }
+        }
+        combined = true;
+    }
+    if combined {
+        log!("Combined multiple {} images into one.", name);
+        Some(DynamicImage::ImageRgba8(res))
+    } else {
+        Some(first)
+    }
+}
+
+/// Prints the recognized input names, the suffix of the texture they go into and what they are for.
+pub fn list_slots(args: &ForwardArgs) {
+    let suffixes = match Suffixes::new(&args.suffix) {
+        Ok(s) => s,
+        Err(e) => {error!("setup", None, "Critical error, {}", e); return;},
+    };
+    println!("input\tsuffix\tslot\tdescription");
+    for input in INPUTS.iter() {
+        let info = input.texture.info();
+        let slot = info.slot.map_or(String::from("-"), |s| s.to_string());
+        let suffix = suffixes.get(input.texture);
+        let overridden = if suffix != info.suffix { " (overridden)" } else { "" };
+        println!("{}\t{}{}\t{}\t{}", input.name, suffix, overridden, slot, input.description);
+    }
+}
+
+pub(crate) fn dds_bytes(tex: &Dds) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    // writing into memory can't fail
+    tex.write(&mut bytes).unwrap();
+    bytes
+}
+
+/// Encodes all textures a second time and compares the bytes with the first encoding.
+fn verify_reproducible(images: &InputImages, args: &ForwardArgs, filter: &OutputFilter, textures: &[(Output, Dds)]) -> bool {
+    log!("Encoding the textures again to verify they are reproducible.");
+    let again = create_textures(images, args, filter, &mut Timings::default());
+    let mut reproducible = textures.len() == again.len();
+    for ((texture, first), (_, second)) in textures.iter().zip(again.iter()) {
+        if dds_bytes(first) != dds_bytes(second) {
+            error!("verify", Some(texture.name().as_str()), "Error: The {} texture is different when encoded again!", texture.name());
+            reproducible = false;
+        }
+    }
+    if reproducible {
+        log!("All textures are byte identical.");
+    }
+    reproducible
+}
+
+/// Reads the image of the input `name` from stdin, it replaces the input directory.
+fn read_stdin_input(name: &str) -> Result<HashMap<String, InputFile>, String> {
+    let input = INPUTS.iter().find(|i| i.name == name).ok_or(format!(
+        "unknown input {} for --stdin, expected one of: {}",
+        name,
+        INPUTS.map(|i| i.name).join(", ")
+    ))?;
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut data)
+        .map_err(|e| format!("cannot read stdin: {}", e))?;
+    let file = InputFile::Archived { name: String::from("<stdin>"), data };
+    Ok(HashMap::from([(input.name.to_owned(), file)]))
+}
+
+/// Writes the only texture to stdout, the conversion has to result in exactly one texture.
+fn write_stdout(textures: Vec<(Output, Dds)>, args: &ForwardArgs) -> bool {
+    if textures.len() != 1 {
+        error!("write", None, "Critical error, --stdout needs exactly one texture, but {} were created!", textures.len());
+        return false;
+    }
+    let (texture, tex) = &textures[0];
+    log!("Writing the {} texture to stdout.", texture.name());
+    let mut stdout = std::io::stdout().lock();
+    let written = match args.container {
+        Container::Dds => stdout.write_all(&dds_file_bytes(tex, args)).map_err(|e| e.to_string()),
+        Container::Ktx2 => write_ktx2(tex, &mut stdout),
+    };
+    if let Err(e) = written.and_then(|_| std::io::Write::flush(&mut stdout).map_err(|e| e.to_string())) {
+        error!("write", None, "Error, cannot write the texture to stdout! {}", e);
+        return false;
+    }
+    true
+}
+
+/// The size, mipmap count and compression of an encoded texture, such as "2048x2048, 12 mips, BC7".
+pub(crate) fn describe_texture(tex: &Dds) -> String {
+    let format = tex
+        .get_dxgi_format()
+        .map(|f| {
+            let name = format!("{:?}", f);
+            let base = name.split('_').next().unwrap_or_default().to_owned();
+            match name {
+                _ if name.ends_with("SNorm") => base + " signed",
+                // ddsfile reports the legacy DXT formats as sRGB, only the DX10 header stores a color space
+                _ if name.ends_with("sRGB") && tex.header10.is_some() => base + " sRGB",
+                _ => base,
+            }
+        })
+        .unwrap_or_else(|| String::from("unknown format"));
+    let mips = tex.get_num_mipmap_levels();
+    format!(
+        "{}x{}, {} {}, {}",
+        tex.get_width(),
+        tex.get_height(),
+        mips,
+        if mips == 1 { "mip" } else { "mips" },
+        format
+    )
+}
+
+/// Whether both paths point to the same directory.
+pub(crate) fn same_dir(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Writes into a temporary file next to `path` and renames it into place once complete,
+/// so an interrupted run never leaves a truncated file behind.
+pub(crate) fn write_atomically<F>(path: &Path, write: F) -> Result<(), String>
+where
+    F: FnOnce(&mut File) -> Result<(), String>,
+{
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let (tmp_path, path) = (long_path(&tmp_path), long_path(path));
+    let result = File::create(&tmp_path)
+        .map_err(|e| e.to_string())
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all().map_err(|e| e.to_string())
+        })
+        .and_then(|_| std::fs::rename(&tmp_path, &path).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Windows limits paths to MAX_PATH (260 characters, 248 for directories) unless they are absolute and start with \\?\.
+/// Long paths get that prefix on Windows, everywhere else and for short paths the path is returned as it is.
+/// Only the file system calls get the prefixed path, messages show the path as given.
+pub fn long_path(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        const MAX_DIR_PATH: usize = 248;
+        if path.as_os_str().len() >= MAX_DIR_PATH {
+            if let Some(absolute) = std::path::absolute(path).ok().and_then(|p| p.to_str().map(str::to_owned)) {
+                return PathBuf::from(match absolute {
+                    _ if absolute.starts_with(r"\\?\") => absolute,
+                    _ if absolute.starts_with(r"\\") => format!(r"\\?\UNC\{}", &absolute[2..]),
+                    _ => format!(r"\\?\{}", absolute),
+                });
+            }
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Writes and removes a small file, to find a read-only or full output directory before anything is encoded.
+pub(crate) fn probe_writable(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(".dmc_probe.tmp");
+    write_atomically(&probe, |file| file.write_all(b"dmc").map_err(|e| e.to_string()))?;
+    std::fs::remove_file(long_path(&probe)).map_err(|e| e.to_string())
+}
+
+/// The name of the texture in the manifests, the texture type or pack and the pack suffix.
+fn manifest_key(texture: &Output) -> String {
+    match texture {
+        Output::Slot(t) => t.info().name.to_owned(),
+        Output::Pack(suffix) => format!("pack{}", suffix),
+    }
+}
+
+/// The path relative to the output directory with / separators on every platform.
+fn manifest_path(path: &Path, out_dir: &Path) -> String {
+    let relative = path.strip_prefix(out_dir).unwrap_or(path);
+    let relative: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    relative.join("/")
+}
+
+fn write_manifest_file(path: &Path, text: &str) {
+    log!("Writing: {}", path.display());
+    if let Err(e) = write_atomically(path, |file| file.write_all(text.as_bytes()).map_err(|e| e.to_string())) {
+        error!("write", Some(path.display().to_string().as_str()), "Error, cannot write the manifest {}! {}", path.display(), e);
+    }
+}
+
+/// Writes the written textures in slot order as lines of texture=path, the paths relative to the output directory.
+fn write_txt_manifest(path: &Path, written: &[(Output, PathBuf)], out_dir: &Path) {
+    let mut text = String::new();
+    for (texture, texture_path) in written {
+        text += &format!("{}={}\n", manifest_key(texture), manifest_path(texture_path, out_dir));
+    }
+    write_manifest_file(path, &text);
+}
+
+/// The size of the longer side of the --preview thumbnails in pixels.
+const PREVIEW_SIZE: u32 = 256;
+const PREVIEW_COLUMNS: u32 = 4;
+
+/// The --preview thumbnails in slot order, labeled with the file names of the textures.
+fn preview_thumbnails(textures: &[(Output, Dds)], suffixes: &Suffixes, args: &ForwardArgs) -> Vec<(String, image::RgbaImage)> {
+    textures
+        .iter()
+        .filter_map(|(texture, tex)| {
+            let label = args.name.clone() + texture.suffix(suffixes) + "." + args.container.extension();
+            // Dds can't be cloned, the copy is read back from the bytes that are written
+            let copy = match Dds::read(Cursor::new(dds_file_bytes(tex, args))) {
+                Ok(copy) => copy,
+                Err(e) => {
+                    error!("verify", Some(texture.name().as_str()), "Error, can't read back the {} texture for the preview: {}", texture.name(), e);
+                    return None;
+                }
+            };
+            Some((label, crate::backward::thumbnail(&texture.name(), copy, PREVIEW_SIZE)?))
+        })
+        .collect()
+}
+
+fn write_preview(path: &Path, thumbs: &[(String, image::RgbaImage)]) {
+    let sheet = DynamicImage::ImageRgba8(crate::contact_sheet::compose(thumbs, PREVIEW_SIZE, PREVIEW_COLUMNS));
+    log!("Writing: {} ({} textures)", path.display(), thumbs.len());
+    let written = write_atomically(path, |file| {
+        sheet.write_to(file, image::ImageOutputFormat::Png).map_err(|e| e.to_string())
+    });
+    if let Err(e) = written {
+        error!("write", Some(path.display().to_string().as_str()), "Error, cannot write the preview {}! {}", path.display(), e);
+    }
+}
+
+/// One written texture in the --manifest.
+#[derive(serde::Serialize)]
+struct ManifestEntry {
+    texture: String,
+    path: String,
+    format: String,
+    width: u32,
+    height: u32,
+    mipmaps: u32,
+    /// The bytes of the written file.
+    file_size: u64,
+    /// The bytes of every mipmap of every layer as uncompressed RGBA8.
+    uncompressed_size: u64,
+    /// The uncompressed size by the stored size of the pixels, without the header.
+    compression_ratio: f64,
+    /// The bits the format stores per pixel.
+    bits_per_pixel: f64,
+}
+
+impl ManifestEntry {
+    fn new(texture: &Output, tex: &Dds, path: &Path, out_dir: &Path) -> ManifestEntry {
+        let (width, height) = (tex.get_width(), tex.get_height());
+        let mipmaps = tex.get_num_mipmap_levels().max(1);
+        let layers = tex.get_num_array_layers().max(1) as u64;
+        let uncompressed_size = (0..mipmaps).map(|level| (width >> level).max(1) as u64 * (height >> level).max(1) as u64 * 4).sum::<u64>() * layers;
+        let stored = vram_bytes(tex).max(1);
+        let bits_per_pixel = match tex.get_dxgi_format().and_then(block_bytes) {
+            Some((bytes, true)) => bytes as f64 * 8.0 / 16.0,
+            Some((bytes, false)) => bytes as f64 * 8.0,
+            None => stored as f64 * 8.0 * 4.0 / uncompressed_size.max(1) as f64,
+        };
+        ManifestEntry {
+            texture: manifest_key(texture),
+            path: manifest_path(path, out_dir),
+            // as in describe_texture, only the DX10 header stores a color space
+            format: tex.get_dxgi_format().map_or(String::from("unknown"), |f| match format!("{:?}", f) {
+                name if tex.header10.is_none() => name.trim_end_matches("_sRGB").to_owned(),
+                name => name,
+            }),
+            width,
+            height,
+            mipmaps,
+            file_size: std::fs::metadata(long_path(path)).map_or(0, |m| m.len()),
+            uncompressed_size,
+            compression_ratio: uncompressed_size as f64 / stored as f64,
+            bits_per_pixel,
+        }
+    }
+}
+
+/// Writes the written textures in slot order as a JSON array, with their format and compression statistics.
+fn write_json_manifest(path: &Path, entries: &[ManifestEntry]) {
+    write_manifest_file(path, &(serde_json::to_string_pretty(entries).unwrap() + "\n"));
+}
+
+/// Deletes the textures of this name that an earlier run wrote but this one didn't produce.
+/// Only the exact name + suffix of the selected slots is touched, the pack names of earlier runs are unknown.
+fn remove_stale_outputs(
+    args: &ForwardArgs,
+    suffixes: &Suffixes,
+    filter: &OutputFilter,
+    produced: &[Output],
+    out_dir: &Path,
+    type_dirs: &HashMap<TextureType, PathBuf>,
+) {
+    for slot in SLOTS.iter() {
+        let texture = Output::Slot(slot.texture);
+        if produced.contains(&texture) || !filter.selects(&texture) {
+            continue;
+        }
+        let dir = type_dirs.get(&slot.texture).map_or(out_dir, |d| d.as_path());
+        let path = dir.join(args.name.clone() + suffixes.get(slot.texture) + "." + args.container.extension());
+        if !long_path(&path).is_file() {
+            continue;
+        }
+        log!("Removing the stale {} texture {}.", slot.name, path.display());
+        if let Err(e) = std::fs::remove_file(long_path(&path)) {
+            error!("write", Some(path.display().to_string().as_str()), "Error, cannot remove the stale texture {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Reads the written texture back and decodes the full resolution, as the backward conversion would.
+fn verify_written(path: &Path) -> Result<(), String> {
+    let mut file = File::open(long_path(path)).map_err(|e| e.to_string())?;
+    let dds = Dds::read(&mut file).map_err(|e| format!("cannot read it back: {}", e))?;
+    image_from_dds(&dds, 0).map_err(|e| format!("cannot decode it: {}", e))?;
+    Ok(())
+}
+
+/// Quotes the path as one argument of the shell the hook runs in.
+fn shell_quote(path: &Path) -> String {
+    let path = path.display().to_string();
+    if cfg!(windows) {
+        format!("\"{}\"", path)
+    } else {
+        format!("'{}'", path.replace('\'', "'\\''"))
+    }
+}
+
+/// Runs the --post-hook command on a written file and reports its exit status, with its output if it failed.
+fn run_post_hook(hook: &str, path: &Path) {
+    let command = hook.replace("{path}", &shell_quote(path));
+    let output = if cfg!(windows) {
+        std::process::Command::new("cmd").arg("/C").arg(&command).output()
+    } else {
+        std::process::Command::new("sh").arg("-c").arg(&command).output()
+    };
+    match output {
+        Ok(output) if output.status.success() => log!("Post hook succeeded for {}.", path.display()),
+        Ok(output) => {
+            let details = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            error!("write", Some(path.display().to_string().as_str()), "Error, the post hook failed for {} ({}). {}", path.display(), output.status, details);
+        }
+        Err(e) => {
+            error!("write", Some(path.display().to_string().as_str()), "Error, cannot run the post hook {}: {}", command, e);
+        }
+    }
+}
+
+/// Applies the sidecars of the input files and removes them from the files: a slot moves its image to that input,
+/// the color space and format are added before the ones of the command line, so the command line still wins.
+/// Returns the arguments with the added color spaces and formats if there are any.
+fn apply_sidecars(files: &mut HashMap<String, InputFile>, args: &ForwardArgs) -> Option<ForwardArgs> {
+    let sidecar_keys: Vec<String> = files.iter().filter(|(_, f)| f.is_sidecar()).map(|(k, _)| k.clone()).collect();
+    let mut sidecars = HashMap::new();
+    for key in sidecar_keys {
+        let file = files.remove(&key).unwrap();
+        let file_name = file.file_name();
+        match file.read_to_string().and_then(|text| Sidecar::parse(&text)) {
+            Ok(sidecar) => {
+                let image_stem = file_name[..file_name.len() - SIDECAR_EXTENSION.len()].to_owned();
+                sidecars.insert(image_stem, sidecar);
+            }
+            Err(e) => error!("load", Some(file_name.as_str()), "Error reading the sidecar {}, it will be ignored. Details: {}", file_name, e),
+        }
+    }
+    if sidecars.is_empty() {
+        return None;
+    }
+    let stem_of = |file: &InputFile| Path::new(&file.file_name()).file_stem().unwrap_or_default().to_string_lossy().to_string();
+    // slots first, the hints apply to the input the image ends up as
+    let mut keys: Vec<String> = files.keys().cloned().collect();
+    keys.sort();
+    for key in keys {
+        let Some(slot) = sidecars.get(&stem_of(&files[&key])).and_then(|s| s.slot.clone()) else {
+            continue;
+        };
+        if !INPUTS.iter().any(|i| i.name == slot) {
+            error!("load", None, "Error, unknown slot {} in the sidecar of {}, expected one of: {}", slot, files[&key].file_name(), INPUTS.map(|i| i.name).join(", "));
+            continue;
+        }
+        if slot == key {
+            continue;
+        }
+        let file = files.remove(&key).unwrap();
+        log!("Using {} as {} (from its sidecar).", file.file_name(), slot);
+        if let Some(other) = files.insert(slot.clone(), file) {
+            log!("Warning: {} is replaced by the sidecar slot, it will be ignored.", other.file_name());
+        }
+    }
+    let mut colorspace = Vec::new();
+    let mut dxgi_format = Vec::new();
+    for input in INPUTS.iter() {
+        let Some(file) = files.get(input.name) else {
+            continue;
+        };
+        let Some(sidecar) = sidecars.get(&stem_of(file)) else {
+            continue;
+        };
+        let texture = input.texture.info().name;
+        if let Some(space) = &sidecar.colorspace {
+            match format!("{}={}", texture, space).parse::<ColorSpaces>() {
+                Ok(c) => colorspace.push(c),
+                Err(e) => error!("load", Some(file.file_name().as_str()), "Error in the sidecar of {}, {}", file.file_name(), e),
+            }
+        }
+        if let Some(format) = &sidecar.format {
+            match format!("{}={}", texture, format).parse::<DxgiFormatSpec>() {
+                Ok(f) => dxgi_format.push(f),
+                Err(e) => error!("load", Some(file.file_name().as_str()), "Error in the sidecar of {}, {}", file.file_name(), e),
+            }
+        }
+    }
+    if colorspace.is_empty() && dxgi_format.is_empty() {
+        return None;
+    }
+    colorspace.extend(args.colorspace.iter().cloned());
+    dxgi_format.extend(args.dxgi_format.iter().cloned());
+    Some(ForwardArgs { colorspace, dxgi_format, ..args.clone() })
+}
+
+/// The name of the textures for --name-from-diffuse: the stem of the diffuse file without the input name,
+/// so rock01_diffuse.png (with --match-mode contains) gives rock01.
+fn name_from_diffuse(diffuse: Option<&InputFile>) -> String {
+    let Some(file) = diffuse else {
+        log!("Warning: There is no diffuse image to take the name from, the textures are written without a name.");
+        return String::new();
+    };
+    let file_name = file.file_name();
+    let stem = Path::new(&file_name).file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let name = match stem.to_ascii_lowercase().find("diffuse") {
+        Some(i) => stem[..i].to_owned() + &stem[i + "diffuse".len()..],
+        None => stem,
+    };
+    let name = name.trim_matches(|c: char| c == '_' || c == '-' || c == '.' || c.is_whitespace()).to_owned();
+    if name.is_empty() {
+        log!("Warning: {} has no name besides diffuse, the textures are written without a name.", file_name);
+    } else {
+        log!("Naming the textures {} after {}.", name, file_name);
+    }
+    name
+}
+
+/// Returns false when there was nothing to convert or a critical error stopped the conversion.
+pub fn run_forward(args: &ForwardArgs, in_dir: &Path, out_dir: &Path) -> bool {
+    let mut args = args.clone();
+    if args.target_game == Some(TargetGame::Le) && !args.archaic_format {
+        log!("Targeting Skyrim LE, only the archaic formats are used.");
+        args.archaic_format = true;
+    }
+    if args.signed {
+        if let Some(game) = args.target_game {
+            if !game.allows(DxgiFormat::BC5_SNorm) {
+                error!("setup", None, "Critical error, {} can't read the signed formats of --signed!", game.name());
+                return false;
+            }
+        }
+        if args.archaic_format {
+            log!("Warning: The archaic formats have no signed variant, --signed is ignored.");
+        }
+    }
+    let args = &args;
+    let suffixes = match Suffixes::new(&args.suffix) {
+        Ok(s) => s,
+        Err(e) => {error!("setup", None, "Critical error, {}", e); return false;},
+    };
+    for (i, pack) in args.pack.iter().enumerate() {
+        if let Some(texture) = suffixes.used_by(&pack.suffix) {
+            error!("setup", None, "Critical error, the pack suffix {} is already used by {}!", pack.suffix, texture.info().name);
+            return false;
+        }
+        if args.pack[..i].iter().any(|p| p.suffix == pack.suffix) {
+            error!("setup", None, "Critical error, the pack suffix {} is used twice!", pack.suffix);
+            return false;
+        }
+    }
+    if let Some(tag) = args.tag.as_deref().filter(|t| t.len() > TAG_LENGTH) {
+        log!("Warning: The tag is longer than {} bytes, it is cut to {:?}.", TAG_LENGTH, truncate_tag(tag));
+    }
+    if let Some(suffix) = &args.split_diffuse_alpha {
+        if suffix.is_empty() || suffix.contains(['/', '\\']) {
+            error!("setup", None, "Critical error, invalid --split-diffuse-alpha suffix {:?}!", suffix);
+            return false;
+        }
+        if let Some(texture) = suffixes.used_by(suffix) {
+            error!("setup", None, "Critical error, the --split-diffuse-alpha suffix {} is already used by {}!", suffix, texture.info().name);
+            return false;
+        }
+        if args.pack.iter().any(|p| p.suffix == *suffix) {
+            error!("setup", None, "Critical error, the --split-diffuse-alpha suffix {} is already used by a pack!", suffix);
+            return false;
+        }
+    }
+    let filter = match OutputFilter::new(args, &suffixes) {
+        Ok(f) => f,
+        Err(e) => {error!("setup", None, "Critical error, {}", e); return false;},
+    };
+    let mut fnames = match &args.stdin {
+        Some(name) => match read_stdin_input(name) {
+            Ok(fnames) => fnames,
+            Err(e) => {error!("load", None, "Critical error, {}", e); return false;},
+        },
+        None => match get_file_paths(in_dir, args.match_mode){
+            Ok(fnames) => fnames,
+            Err(e) => {error!("setup", None, "Critical error, cannot get file paths: {}", e); return false;},
+        },
+    };
+    let hinted = apply_sidecars(&mut fnames, args);
+    let args = hinted.as_ref().unwrap_or(args);
+    let named;
+    let args = match args.name_from_diffuse && args.name.is_empty() {
+        true => {
+            named = ForwardArgs { name: name_from_diffuse(fnames.get("diffuse")), ..args.clone() };
+            &named
+        }
+        false => args,
     };
-    let images = InputImages {
+    if same_dir(in_dir, out_dir) {
+        log!("Warning: The output directory is the input directory, the textures are written next to the source images.");
+        // earlier outputs would otherwise be picked up as dds inputs
+        let outputs: Vec<String> = SLOTS
+            .iter()
+            .map(|s| args.name.clone() + suffixes.get(s.texture))
+            .chain(args.pack.iter().map(|p| args.name.clone() + &p.suffix))
+            .collect();
+        fnames.retain(|stem, file| {
+            let own = file.is_dds() && outputs.contains(stem);
+            if own {
+                log!("Skipping {}, it is an output of this conversion.", file.file_name());
+            }
+            !own
+        });
+    }
+    if let Some(common_dir) = &args.common_dir {
+        match get_file_paths(common_dir, args.match_mode) {
+            Ok(mut common) => {
+                // only the slots of the common sidecars are used, the hints of common files the material replaces would apply otherwise
+                apply_sidecars(&mut common, args);
+                for (name, file) in common {
+                    if let std::collections::hash_map::Entry::Vacant(entry) = fnames.entry(name) {
+                        if INPUTS.iter().any(|i| i.name == entry.key()) {
+                            log!("Using {} from the common directory.", file.file_name());
+                        }
+                        entry.insert(file);
+                    }
+                }
+            }
+            Err(e) => {error!("setup", None, "Critical error, cannot get file paths of the common directory: {}", e); return false;},
+        }
+    }
+    if args.dry_run {
+        return preflight(&fnames, args);
+    }
+    let mut timings = Timings::default();
+    let load_start = Instant::now();
+    let mut images = InputImages {
         diffuse_alpha: load_input_image(fnames.get("diffuse")),
         normal: load_input_image(fnames.get("normal")),
         specular: load_input_image(fnames.get("specular")),
-        glow: load_input_image(fnames.get("glow")),
+        glow: combine_additive("glow", load_numbered_images(&fnames, "glow")),
         skin_tint: load_input_image(fnames.get("skin_tint")),
         height: load_input_image(fnames.get("height")),
         cubemap: load_input_image(fnames.get("cubemap")),
@@ -452,18 +3144,163 @@ pub fn run_forward(args: &Args, in_dir: &PathBuf, out_dir: &PathBuf){
         backlight: load_input_image(fnames.get("backlight")),
         metallic: load_input_image(fnames.get("metallic")),
         glossiness: load_input_image(fnames.get("glossiness")),
+        roughness: load_input_image(fnames.get("roughness")),
+        ao: load_input_image(fnames.get("ao")),
+        detail_normal: None,
+        source_mips: HashMap::new(),
+    };
+    if args.keep_mips {
+        for input in INPUTS.iter() {
+            // combined glow images have no common mipmaps
+            if input.name == "glow" && fnames.contains_key("glow2") {
+                continue;
+            }
+            if let Some(file) = fnames.get(input.name).filter(|f| f.is_dds()) {
+                if let Some(surface) = load_source_mips(file) {
+                    images.source_mips.insert(input.name, surface);
+                }
+            }
+        }
+    }
+    if let Err(e) = assemble_channel_files(&mut images, &args.channel_file, in_dir) {
+        error!("load", None, "Critical error, {}", e);
+        return false;
+    }
+    if let Some(path) = &args.detail_normal {
+        if images.normal.is_none() {
+            log!("Warning: There is no normal map, the detail normal is not used.");
+        } else if args.detail_tiling <= 0.0 {
+            error!("setup", None, "Critical error, --detail-tiling must be above 0, got {}.", args.detail_tiling);
+            return false;
+        } else {
+            match load_detail_normal(path, in_dir) {
+                Ok(img) => images.detail_normal = Some(img),
+                Err(e) => {
+                    error!("load", None, "Critical error, {}", e);
+                    return false;
+                }
+            }
+        }
+    }
+    timings.load = load_start.elapsed();
+    if images.is_empty() {
+        error!("load", None, "Critical error, no input images found in {}!", in_dir.display());
+        log!("The expected image names (without the file extension) are: {}.", INPUTS.map(|i| i.name).join(", "));
+        return false;
+    }
+    if let Some(size) = args.force_resolution {
+        force_resolution(&mut images, size);
+    }
+    if let Some(mode) = args.square {
+        make_square(&mut images, mode, args.pad_color);
+    }
+    if let Err(e) = masks_to_alpha(&mut images, &args.mask_to_alpha) {
+        error!("load", None, "Critical error, {}", e);
+        return false;
+    }
+    route_gray_alpha(&mut images, args);
+    fill_solid_inputs(&mut images, args);
+    if args.gen_ao {
+        match (&images.ao, &images.height) {
+            (Some(_), _) => log!("Found an ao image, it will be used instead of generating one."),
+            (None, Some(height)) => {
+                log!("Generating ao from height with radius {}.", args.ao_radius);
+                images.ao = Some(generate_ao(height, args.ao_radius));
+            }
+            (None, None) => error!("create", None, "Error: Ao generation selected, but no height image supplied!"),
+        }
+    }
+
+    warn_unused_inputs(&images, args);
+
+    if args.stdout {
+        let textures = create_textures(&images, args, &filter, &mut timings);
+        if args.verify_reproducible && !verify_reproducible(&images, args, &filter, &textures) {
+            return false;
+        }
+        return write_stdout(textures, args);
+    }
+    let out_dir = match game_path_dir(args, out_dir) {
+        Some(dir) => dir,
+        None => return false,
+    };
+    let out_dir = out_dir.as_path();
+    if let Err(e) = probe_writable(out_dir) {
+        error!("write", Some(out_dir.display().to_string().as_str()), "Critical error, the output directory {} is not writable: {}", out_dir.display(), e);
+        return false;
+    }
+    if args.verify && args.container != Container::Dds {
+        log!("Warning: --verify can only read back dds files, the ktx2 textures are not verified.");
+    }
+    let type_dirs = match type_dirs(args, out_dir) {
+        Ok(dirs) => dirs,
+        Err(e) => {error!("setup", None, "Critical error, {}", e); return false;},
     };
 
-    let textures = create_textures(images, &args);
-    for (suffix, tex) in textures {
-        let out_path = out_dir.join(args.name.clone() + suffix + ".dds");
-        println!("Writing: {}", out_path.display());
-        let mut file = match File::create(out_path){
-            Ok(f) => f,
-            Err(e) => {println!("Error, cannot create texture file at {}! {}", out_dir.display(), e); continue;},
+    let textures = create_textures(&images, args, &filter, &mut timings);
+    if args.verify_reproducible && !verify_reproducible(&images, args, &filter, &textures) {
+        return false;
+    }
+    if args.footprint {
+        print_footprint(&textures, args);
+    }
+    let produced: Vec<Output> = textures.iter().map(|(texture, _)| texture.clone()).collect();
+    // decoded before the loop below takes the textures, from the same data that is written
+    let preview = args.preview.then(|| preview_thumbnails(&textures, &suffixes, args));
+    let mut written_paths = Vec::new();
+    let mut manifest_entries = Vec::new();
+    for (texture, tex) in textures {
+        let write_start = Instant::now();
+        let dir = match &texture {
+            Output::Slot(t) => type_dirs.get(t).map_or(out_dir, |d| d.as_path()),
+            Output::Pack(_) => out_dir,
         };
-        if let Err(e) = tex.write(&mut file){
-            println!("Error, cannot write into texture file! {}", e);
+        let out_path = dir.join(args.name.clone() + texture.suffix(&suffixes) + "." + args.container.extension());
+        match texture.slot() {
+            Some(slot) => log!("Writing: {} ({}, texture slot {})", out_path.display(), describe_texture(&tex), slot),
+            None => log!("Writing: {} ({})", out_path.display(), describe_texture(&tex)),
+        }
+        let written = write_atomically(&out_path, |file| match args.container {
+            Container::Dds => file.write_all(&dds_file_bytes(&tex, args)).map_err(|e| e.to_string()),
+            Container::Ktx2 => write_ktx2(&tex, file),
+        });
+        if let Err(e) = written {
+            error!("write", Some(out_path.display().to_string().as_str()), "Error, cannot write the texture file {}! {}", out_path.display(), e);
+            // the following files would fail the same way if the directory became read-only or the disk is full
+            if let Err(e) = probe_writable(out_dir) {
+                error!("write", Some(out_dir.display().to_string().as_str()), "Critical error, the output directory {} is no longer writable: {}. The remaining textures are not written!", out_dir.display(), e);
+                return false;
+            }
+        } else {
+            if args.verify && args.container == Container::Dds {
+                if let Err(e) = verify_written(&out_path) {
+                    error!("verify", Some(out_path.display().to_string().as_str()), "Error, the written texture {} is corrupt, {}!", out_path.display(), e);
+                }
+            }
+            if let Some(hook) = &args.post_hook {
+                run_post_hook(hook, &out_path);
+            }
+            if args.manifest.is_some() {
+                manifest_entries.push(ManifestEntry::new(&texture, &tex, &out_path, out_dir));
+            }
+            written_paths.push((texture.clone(), out_path.clone()));
         }
+        timings.write.push((texture, write_start.elapsed()));
+    }
+    if let Some(manifest) = &args.txt_manifest {
+        write_txt_manifest(&out_dir.join(manifest), &written_paths, out_dir);
+    }
+    if let Some(manifest) = &args.manifest {
+        write_json_manifest(&out_dir.join(manifest), &manifest_entries);
     }
-}
\ No newline at end of file
+    if let Some(thumbs) = preview {
+        write_preview(&out_dir.join(args.name.clone() + "preview.png"), &thumbs);
+    }
+    if args.clean {
+        remove_stale_outputs(args, &suffixes, &filter, &produced, out_dir, &type_dirs);
+    }
+    if args.timing {
+        timings.print();
+    }
+    true
+}
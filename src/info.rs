@@ -0,0 +1,75 @@
+use crate::backward::get_dds_file_paths;
+use crate::forward::{long_path, read_tag};
+use crate::InfoArgs;
+use image_dds::ddsfile::{Caps2, Dds, MiscFlag};
+use std::path::Path;
+
+fn describe_format(tex: &Dds) -> String {
+    if let (Some(f), Some(_)) = (tex.get_dxgi_format(), &tex.header10) {
+        format!("DXGI {:?} (DX10 header)", f)
+    } else if let Some(f) = tex.get_d3d_format() {
+        format!("D3D {:?} (legacy header)", f)
+    } else if let Some(fourcc) = &tex.header.spf.fourcc {
+        let code: String = fourcc.0.to_le_bytes().iter().map(|&b| b as char).collect();
+        format!("FourCC {} (legacy header)", code)
+    } else {
+        String::from("unknown")
+    }
+}
+
+pub(crate) fn is_cubemap(tex: &Dds) -> bool {
+    tex.header.caps2.contains(Caps2::CUBEMAP)
+        || matches!(&tex.header10, Some(h) if h.misc_flag.contains(MiscFlag::TEXTURECUBE))
+}
+
+/// Returns false when the texture can't be read.
+fn print_info(name: &str, path: &Path) -> bool {
+    // The whole file is read for the tag behind the header, but the texture data is never decoded.
+    let file = match std::fs::read(long_path(path)) {
+        Ok(f) => f,
+        Err(e) => {error!("load", Some(path.display().to_string().as_str()), "Error, can't open {}: {}", path.display(), e); return false;},
+    };
+    let tex = match Dds::read(file.as_slice()) {
+        Ok(t) => t,
+        Err(e) => {error!("load", Some(path.display().to_string().as_str()), "Error, can't read dds at {}: {}", path.display(), e); return false;},
+    };
+    println!("{}:", name);
+    println!("  size:        {}x{}", tex.get_width(), tex.get_height());
+    if tex.get_depth() > 1 {
+        println!("  depth:       {}", tex.get_depth());
+    }
+    println!("  mipmaps:     {}", tex.get_num_mipmap_levels());
+    println!("  format:      {}", describe_format(&tex));
+    println!("  array size:  {}", tex.get_num_array_layers());
+    println!("  cubemap:     {}", if is_cubemap(&tex) { "yes" } else { "no" });
+    if let Some(tag) = read_tag(&file) {
+        println!("  tag:         {}", tag);
+    }
+    true
+}
+
+/// Returns false when there was no texture to inspect, one of them couldn't be read or a critical error stopped it.
+pub fn run_info(args: &InfoArgs, in_dir: &Path) -> bool {
+    if let Some(path) = &args.path {
+        if long_path(path).is_file() {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            return print_info(&name, path);
+        }
+    }
+    let dir = args.path.as_deref().unwrap_or(in_dir);
+    let paths = match get_dds_file_paths(dir) {
+        Ok(p) => p,
+        Err(e) => {error!("setup", None, "Critical error, cannot get file paths: {}", e); return false;},
+    };
+    if paths.is_empty() {
+        println!("No dds files found in {}.", dir.display());
+        return false;
+    }
+    let mut paths: Vec<_> = paths.into_iter().collect();
+    paths.sort();
+    let mut success = true;
+    for (name, path) in paths {
+        success &= print_info(&name, &path);
+    }
+    success
+}
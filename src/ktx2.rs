@@ -0,0 +1,196 @@
+//! Writes the encoded surfaces of a dds texture into a KTX2 container.
+//! See https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html for the layout.
+use image_dds::ddsfile::{Caps2, Dds, DxgiFormat, MiscFlag};
+use std::io::Write;
+
+const IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const HEADER_LEN: usize = 12 + 9 * 4 + 4 * 4 + 2 * 8;
+
+// data format descriptor channel ids
+const CHANNEL_COLOR: u8 = 0;
+const CHANNEL_GREEN: u8 = 1;
+const CHANNEL_BLUE: u8 = 2;
+const CHANNEL_ALPHA: u8 = 15;
+/// The channel of BC1 blocks that can be transparent.
+const CHANNEL_BC1_ALPHA: u8 = 1;
+const CHANNEL_SIGNED: u8 = 0x40;
+const CHANNEL_FLOAT: u8 = 0x80;
+
+/// How a dxgi format is described in KTX2.
+struct FormatInfo {
+    vk_format: u32,
+    color_model: u8,
+    srgb: bool,
+    /// Bytes per 4x4 block, or per pixel if the format is not block compressed.
+    block_bytes: u32,
+    block_compressed: bool,
+    /// Channel id and bit length of each sample.
+    samples: &'static [(u8, u8)],
+}
+
+/// `bc1_alpha` describes BC1 as the format with 1 bit alpha.
+fn format_info(format: DxgiFormat, bc1_alpha: bool) -> Option<FormatInfo> {
+    let bc = |vk_format, color_model, srgb, block_bytes, samples| FormatInfo {
+        vk_format,
+        color_model,
+        srgb,
+        block_bytes,
+        block_compressed: true,
+        samples,
+    };
+    let rgba8 = |vk_format, srgb| FormatInfo {
+        vk_format,
+        color_model: 1,
+        srgb,
+        block_bytes: 4,
+        block_compressed: false,
+        samples: &[(CHANNEL_COLOR, 8), (CHANNEL_GREEN, 8), (CHANNEL_BLUE, 8), (CHANNEL_ALPHA, 8)],
+    };
+    Some(match format {
+        DxgiFormat::BC1_UNorm if bc1_alpha => bc(133, 128, false, 8, &[(CHANNEL_BC1_ALPHA, 64)]),
+        DxgiFormat::BC1_UNorm_sRGB if bc1_alpha => bc(134, 128, true, 8, &[(CHANNEL_BC1_ALPHA, 64)]),
+        DxgiFormat::BC1_UNorm => bc(131, 128, false, 8, &[(CHANNEL_COLOR, 64)]),
+        DxgiFormat::BC1_UNorm_sRGB => bc(132, 128, true, 8, &[(CHANNEL_COLOR, 64)]),
+        DxgiFormat::BC2_UNorm => bc(135, 129, false, 16, &[(CHANNEL_ALPHA, 64), (CHANNEL_COLOR, 64)]),
+        DxgiFormat::BC2_UNorm_sRGB => bc(136, 129, true, 16, &[(CHANNEL_ALPHA, 64), (CHANNEL_COLOR, 64)]),
+        DxgiFormat::BC3_UNorm => bc(137, 130, false, 16, &[(CHANNEL_ALPHA, 64), (CHANNEL_COLOR, 64)]),
+        DxgiFormat::BC3_UNorm_sRGB => bc(138, 130, true, 16, &[(CHANNEL_ALPHA, 64), (CHANNEL_COLOR, 64)]),
+        DxgiFormat::BC4_UNorm => bc(139, 131, false, 8, &[(CHANNEL_COLOR, 64)]),
+        DxgiFormat::BC4_SNorm => bc(140, 131, false, 8, &[(CHANNEL_COLOR | CHANNEL_SIGNED, 64)]),
+        DxgiFormat::BC5_UNorm => bc(141, 132, false, 16, &[(CHANNEL_COLOR, 64), (CHANNEL_GREEN, 64)]),
+        DxgiFormat::BC5_SNorm => bc(
+            142,
+            132,
+            false,
+            16,
+            &[(CHANNEL_COLOR | CHANNEL_SIGNED, 64), (CHANNEL_GREEN | CHANNEL_SIGNED, 64)],
+        ),
+        DxgiFormat::BC6H_UF16 => bc(143, 133, false, 16, &[(CHANNEL_COLOR | CHANNEL_FLOAT, 128)]),
+        DxgiFormat::BC6H_SF16 => bc(144, 133, false, 16, &[(CHANNEL_COLOR | CHANNEL_FLOAT | CHANNEL_SIGNED, 128)]),
+        DxgiFormat::BC7_UNorm => bc(145, 134, false, 16, &[(CHANNEL_COLOR, 128)]),
+        DxgiFormat::BC7_UNorm_sRGB => bc(146, 134, true, 16, &[(CHANNEL_COLOR, 128)]),
+        DxgiFormat::R8G8B8A8_UNorm => rgba8(37, false),
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => rgba8(43, true),
+        _ => return None,
+    })
+}
+
+/// The basic data format descriptor, including the leading total size.
+fn data_format_descriptor(info: &FormatInfo) -> Vec<u8> {
+    let block_len = 24 + 16 * info.samples.len();
+    let mut dfd = Vec::with_capacity(4 + block_len);
+    dfd.extend(((4 + block_len) as u32).to_le_bytes());
+    dfd.extend(0u32.to_le_bytes()); // vendor id and descriptor type
+    dfd.extend(2u16.to_le_bytes()); // version
+    dfd.extend((block_len as u16).to_le_bytes());
+    dfd.push(info.color_model);
+    dfd.push(1); // BT.709 primaries
+    dfd.push(if info.srgb { 2 } else { 1 }); // transfer function
+    dfd.push(0); // straight alpha
+    let dim = if info.block_compressed { 3 } else { 0 };
+    dfd.extend([dim, dim, 0, 0]);
+    dfd.push(info.block_bytes as u8);
+    dfd.extend([0; 7]);
+    let mut bit_offset = 0u16;
+    for &(channel, bits) in info.samples {
+        let linear = info.srgb && channel == CHANNEL_ALPHA;
+        dfd.extend(bit_offset.to_le_bytes());
+        dfd.push(bits - 1);
+        dfd.push(channel | if linear { 0x10 } else { 0 });
+        dfd.extend([0; 4]); // sample position
+        let (lower, upper): (u32, u32) = if info.block_compressed {
+            (0, u32::MAX)
+        } else {
+            (0, (1 << bits) - 1)
+        };
+        dfd.extend(lower.to_le_bytes());
+        dfd.extend(upper.to_le_bytes());
+        bit_offset += bits as u16;
+    }
+    dfd
+}
+
+fn level_len(info: &FormatInfo, width: u32, height: u32, level: u32) -> usize {
+    let w = (width >> level).max(1);
+    let h = (height >> level).max(1);
+    if info.block_compressed {
+        (w.div_ceil(4) * h.div_ceil(4) * info.block_bytes) as usize
+    } else {
+        (w * h * info.block_bytes) as usize
+    }
+}
+
+/// Whether any BC1 block is in the three color mode (the first endpoint not above the second) and uses the transparent index 3.
+fn has_transparent_blocks(data: &[u8]) -> bool {
+    data.chunks_exact(8).any(|block| {
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        c0 <= c1 && (0..16).any(|i| (indices >> (i * 2)) & 3 == 3)
+    })
+}
+
+/// Writes the texture as KTX2. Only textures with a DX10 header (as created by image_dds) are supported.
+pub fn write_ktx2<W: Write>(tex: &Dds, writer: &mut W) -> Result<(), String> {
+    let format = tex
+        .get_dxgi_format()
+        .ok_or_else(|| String::from("the texture has no dxgi format"))?;
+    let bc1_alpha = matches!(format, DxgiFormat::BC1_UNorm | DxgiFormat::BC1_UNorm_sRGB) && has_transparent_blocks(&tex.data);
+    let info = format_info(format, bc1_alpha).ok_or_else(|| format!("{:?} is not supported in KTX2", format))?;
+    let (width, height) = (tex.get_width(), tex.get_height());
+    let levels = tex.get_num_mipmap_levels().max(1);
+    let cubemap = tex.header.caps2.contains(Caps2::CUBEMAP)
+        || tex.header10.as_ref().is_some_and(|h| h.misc_flag.contains(MiscFlag::TEXTURECUBE));
+    let faces = if cubemap { 6 } else { 1 };
+    // the dx10 array size counts whole cubemaps
+    let layers = tex.header10.as_ref().map_or(1, |h| h.array_size.max(1));
+    let surfaces = (layers * faces) as usize;
+
+    // dds stores all mipmaps of a surface together, KTX2 all surfaces of a mipmap
+    let level_lens: Vec<usize> = (0..levels).map(|l| level_len(&info, width, height, l)).collect();
+    let surface_len: usize = level_lens.iter().sum();
+    if tex.data.len() < surface_len * surfaces {
+        return Err(String::from("the texture data is shorter than its header describes"));
+    }
+
+    let dfd = data_format_descriptor(&info);
+    let level_index_len = 24 * levels as usize;
+    let dfd_offset = HEADER_LEN + level_index_len;
+    let align = info.block_bytes as usize;
+    let data_start = (dfd_offset + dfd.len()).div_ceil(align) * align;
+
+    // the smallest mipmap comes first in the file
+    let mut offsets = vec![0; levels as usize];
+    let mut offset = data_start;
+    for l in (0..levels as usize).rev() {
+        offsets[l] = offset;
+        offset += level_lens[l] * surfaces;
+    }
+
+    let mut out = Vec::with_capacity(offset);
+    out.extend(IDENTIFIER);
+    let layer_count = if layers > 1 { layers } else { 0 };
+    for v in [info.vk_format, 1, width, height, 0, layer_count, faces, levels, 0] {
+        out.extend(v.to_le_bytes());
+    }
+    for v in [dfd_offset as u32, dfd.len() as u32, 0, 0] {
+        out.extend(v.to_le_bytes());
+    }
+    out.extend([0; 16]); // no supercompression global data
+    for l in 0..levels as usize {
+        let len = (level_lens[l] * surfaces) as u64;
+        out.extend((offsets[l] as u64).to_le_bytes());
+        out.extend(len.to_le_bytes());
+        out.extend(len.to_le_bytes());
+    }
+    out.extend(&dfd);
+    out.resize(data_start, 0);
+    for l in (0..levels as usize).rev() {
+        let level_start: usize = level_lens[..l].iter().sum();
+        for s in 0..surfaces {
+            let start = s * surface_len + level_start;
+            out.extend(&tex.data[start..start + level_lens[l]]);
+        }
+    }
+    writer.write_all(&out).map_err(|e| e.to_string())
+}
@@ -0,0 +1,331 @@
+//! Conversion between images and Skyrim dds textures. The command line lives in main.rs, the conversions are here so they can be used on in-memory images.
+use argh::FromArgs;
+
+use std::path::PathBuf;
+
+#[macro_use]
+pub mod log;
+pub mod forward;
+pub mod backward;
+pub mod bench;
+mod contact_sheet;
+pub mod info;
+mod ktx2;
+pub mod organize;
+pub mod settings;
+pub mod slots;
+pub mod test_matrix;
+use forward::{Channel, ChannelFile, ColorSpaces, DxgiFormatSpec, PitchMode, Container, EncodeQuality, MatchMode, PackSpec, PadColor, Resolution, SquareMode, TargetGame};
+use backward::{OutputFormat, PngCompression, SplitGrid};
+
+#[derive(FromArgs, Default, Clone)]
+#[argh(subcommand, name = "forward")]
+/// convert images to dds textures (the default mode).
+pub struct ForwardArgs {
+    #[argh(option, short = 'n', default = "String::from(\"\")")]
+    /// the name of the resulting textures. For example, the normal map file will be named name_n.dds
+    pub name: String,
+    #[argh(switch)]
+    /// when no --name is given, name the textures after the diffuse file, without the word diffuse: rock01_diffuse.png gives rock01.dds, rock01_n.dds, ... Useful with --match-mode contains
+    pub name_from_diffuse: bool,
+    #[argh(switch, short = 'h')]
+    /// force diffuse textures to use BC7 instead of BC1 (normals always use BC7). BC7 should better represent subtle changes or gradients, but uses significantly more space
+    pub high_quality: bool,
+    #[argh(switch, short = 'a')]
+    /// only use older formats (BC1 and BC3) compatible with Skyrim LE. Only use if you target games that do not support BC4 and BC7
+    pub archaic_format: bool,
+    #[argh(option)]
+    /// the game the textures are for: le, se or fo4. Textures in a format the game can't read are skipped with an error, le implies --archaic-format
+    pub target_game: Option<TargetGame>,
+    #[argh(option)]
+    /// the color space of textures: srgb or linear, for example --colorspace diffuse=srgb,glow=linear. Srgb selects the sRGB variant of BC1, BC3, BC7 and uncompressed, BC4 and BC5 have none. Linear by default
+    pub colorspace: Vec<ColorSpaces>,
+    #[argh(option)]
+    /// the exact format of a texture instead of the picked one, for example --dxgi-format normal=BC5_UNORM or diffuse=R16G16B16A16_FLOAT. Overrides -h, -a, --signed and --colorspace for that texture
+    pub dxgi_format: Vec<DxgiFormatSpec>,
+    #[argh(switch, short = 't')]
+    /// will write height information instead of transparency to the alpha channel of the diffuse texture. Used for parallax on landscape/terrain textures.
+    pub terrain_parallax: bool,
+    #[argh(switch, short = 'c')]
+    /// will write complex parallax information (R: env_mask, G: glossiness, B: metallic, A: height) into the environment map. Used for parallax on object textures. The height is still written as the separate BC4 _p texture as well
+    pub complex_parallax: bool,
+    #[argh(option, default = "Channel::R")]
+    /// the channel (r, g, b or a) of the height image that is packed into the alpha of the diffuse or environment mask. Red by default
+    pub height_channel: Channel,
+    #[argh(option, default = "Channel::R")]
+    /// the channel (r, g, b or a) of the specular image that is packed into the alpha of the normal map. Red by default
+    pub specular_channel: Channel,
+    #[argh(switch)]
+    /// pack the luminance of the specular image into the alpha of the normal map instead of --specular-channel, for colored specular maps
+    pub specular_luma: bool,
+    #[argh(option, default = "settings::env_quality()")]
+    /// encoder quality of BC7 textures: fast, normal or slow. BC7 encoding takes most of the time, fast is much quicker with a small loss of detail. DMC_QUALITY or slow by default
+    pub quality_bc7: EncodeQuality,
+    #[argh(option, default = "settings::env_quality()")]
+    /// encoder quality of the other formats: fast, normal or slow. DMC_QUALITY or slow by default. The current BC1 to BC5 encoders have a single speed, so it only matters for future encoders
+    pub quality_other: EncodeQuality,
+    #[argh(option)]
+    /// encoder quality of BC7 diffuse textures with alpha: fast, normal or slow, for cutouts like foliage and hair whose alpha edges need more care than the color. BC7 has a single quality for all channels, so it is used for the whole texture when it is higher than --quality-bc7
+    pub alpha_quality: Option<EncodeQuality>,
+    #[argh(option)]
+    /// true to always write the DX10 extended header, false to use the legacy header where the format allows it. By default only formats that require it (BC7) get the DX10 header
+    pub dx10_header: Option<bool>,
+    #[argh(option, default = "PitchMode::Auto")]
+    /// what the pitch field of the dds header holds: auto (the linear size for compressed, the row pitch for uncompressed textures, as the DDS documentation asks), pitch, linear-size or none. The games recompute it, only change it for strict loaders that reject the files
+    pub pitch_mode: PitchMode,
+    #[argh(option)]
+    /// a short text (up to 32 bytes) written with the signature of this tool into the reserved fields of the dds header, such as the settings or an asset id. Shown by info, for tracking where textures came from
+    pub tag: Option<String>,
+    #[argh(switch)]
+    /// write a texture uncompressed (R8G8B8A8) when encoding it in its format fails, instead of skipping it
+    pub fallback_uncompressed: bool,
+    #[argh(option, default = "Container::Dds")]
+    /// the file format the textures are written in: dds or ktx2. Dds by default
+    pub container: Container,
+    #[argh(option)]
+    /// assemble an input from separate grayscale files, one per channel, for example --channel-file env_mask.r=mask.png. The first channel of each file is used, missing channels are black and alpha is opaque. Can be repeated
+    pub channel_file: Vec<ChannelFile>,
+    #[argh(option)]
+    /// write the luminance of one input into the alpha of another, for example --mask-to-alpha env_mask=diffuse. For masks made as black and white color images. Can be repeated
+    pub mask_to_alpha: Vec<String>,
+    #[argh(option)]
+    /// write an extra texture with channels copied from the inputs, for example R=metallic.r,G=ao.r,B=height.r,A=glossiness.r:suffix=_rma. Unmapped channels are black, alpha is opaque. Can be repeated
+    pub pack: Vec<PackSpec>,
+    #[argh(switch)]
+    /// encode normal maps as two channel BC5 (X and Y only), which many engines prefer. BC5 has no alpha, so the specular is not packed into the normal map and only the separate _s texture is written
+    pub normal_bc5: bool,
+    #[argh(switch)]
+    /// write the BC4 and BC5 textures with signed normalization (BC4_SNORM, BC5_SNORM), mapping 0..255 to -1..1. None of the target games read signed formats, so it can't be combined with --target-game
+    pub signed: bool,
+    #[argh(switch)]
+    /// write the normal map with an opaque alpha instead of packing the specular into it, for inspecting the plain normals. The _s texture is still written
+    pub flatten_normal_alpha: bool,
+    #[argh(switch)]
+    /// with --archaic-format, store normal maps as BC3n (X in alpha, Y in green, red and blue empty) as some Skyrim LE shaders expect. The specular is then only written to the separate _s texture
+    pub bc3n: bool,
+    #[argh(switch)]
+    /// rescale the normal vectors to unit length before encoding. Fixes shading errors of normal maps that were edited or resized
+    pub normalize_normal: bool,
+    #[argh(switch)]
+    /// invert the blue (Z) channel of the normal map when its mean is below 128, which means the export points the normals into the surface. Without it only a warning is printed
+    pub fix_normal_z: bool,
+    #[argh(option)]
+    /// a tiling detail normal map blended into the normal map with the partial derivative blend, for layered materials. The path is relative to the input directory unless it exists as given
+    pub detail_normal: Option<PathBuf>,
+    #[argh(option, default = "1.0")]
+    /// how strongly the detail normal bends the normals, 1 by default. 0 leaves the normal map unchanged
+    pub detail_strength: f32,
+    #[argh(option, default = "1.0")]
+    /// how many times the detail normal repeats per its own size, 2 tiles it twice as often. The detail is repeated across the normal map whatever their resolutions, 1 by default
+    pub detail_tiling: f32,
+    #[argh(switch)]
+    /// ignore the alpha of the diffuse image and encode it as opaque, so opaque materials get BC1 (or BC7 with -h) instead of a format with alpha. Terrain parallax still writes the height into the alpha
+    pub drop_diffuse_alpha: bool,
+    #[argh(option)]
+    /// also write the alpha of the diffuse as a separate grayscale (BC4) texture with this suffix, for example _a, when the diffuse isn't opaque. Add --drop-diffuse-alpha to remove it from the diffuse
+    pub split_diffuse_alpha: Option<String>,
+    #[argh(option)]
+    /// force the alpha of these outputs to opaque before encoding, so a stray alpha channel (a glow map exported with alpha) doesn't reach the game. A comma separated list like --only, it applies to the diffuse and the textures made from a single image (glow, skin_tint, height, env_mask, specular, backlight, ao, cubemap). Can be repeated
+    pub opaque_alpha: Vec<String>,
+    #[argh(switch)]
+    /// fill the color of fully transparent diffuse pixels from their opaque neighbors before mipmaps are generated. Removes dark halos around alpha tested textures (foliage, hair) at a distance
+    pub dilate_alpha: bool,
+    #[argh(switch)]
+    /// divide the diffuse colors by their alpha before encoding, for exporters that premultiply the alpha into the colors. Premultiplied colors cause dark halos in game
+    pub unpremultiply: bool,
+    #[argh(switch)]
+    /// multiply the diffuse colors by their alpha before encoding, for shaders that expect premultiplied alpha
+    pub premultiply: bool,
+    #[argh(switch)]
+    /// use the alpha channel of a grayscale+alpha specular image as glossiness, unless a glossiness image is supplied. Otherwise the alpha of grayscale+alpha images is discarded in single channel textures
+    pub specular_alpha_gloss: bool,
+    #[argh(switch)]
+    /// write the _s texture as complex skin specular: R holds the specular, G the glossiness (or the inverted roughness image). A missing one is filled with --specular-value (255 by default) or --glossiness-value (128 by default)
+    pub skin_specular: bool,
+    #[argh(option)]
+    /// make off-square inputs (like 2048x2046 from a bad export) square before they are packed: crop keeps the center at the shorter side, pad extends the shorter side to the longer one (see --pad-color)
+    pub square: Option<SquareMode>,
+    #[argh(option)]
+    /// resize every input to this resolution (width x height, for example 2048x2048) right after loading, with a Lanczos filter. Evens out the sizes of a material set and of images packed together, upscaling only blurs
+    pub force_resolution: Option<Resolution>,
+    #[argh(option)]
+    /// the color (RRGGBB or RRGGBBAA hex) of padded borders, such as with --square pad. By default the edge pixels are repeated. Grayscale images use the red value
+    pub pad_color: Option<PadColor>,
+    #[argh(switch)]
+    /// generate a flat placeholder normal map (128, 128, 255) at the diffuse resolution when there is no normal image
+    pub default_normal: bool,
+    #[argh(option)]
+    /// a solid specular value (0-255) used when there is no specular image. It is created at the resolution of the normal map
+    pub specular_value: Option<u8>,
+    #[argh(option)]
+    /// a solid metallic value (0-255) used when there is no metallic image
+    pub metallic_value: Option<u8>,
+    #[argh(option)]
+    /// a solid glossiness value (0-255) used when there is no glossiness image
+    pub glossiness_value: Option<u8>,
+    #[argh(switch)]
+    /// generate an ambient occlusion texture (_ao) from the height image, unless an ao image is supplied. A rough approximation for previews, a baked ao is better
+    pub gen_ao: bool,
+    #[argh(option, default = "4")]
+    /// radius in pixels of the neighborhood that the generated ambient occlusion compares the height against, 4 by default
+    pub ao_radius: u32,
+    #[argh(option)]
+    /// relative path of the textures inside the game's Textures folder, for example armor/iron. The textures are then written to output/Textures/armor/iron, ready to be packed into a .bsa or .ba2 archive
+    pub game_path: Option<PathBuf>,
+    #[argh(option)]
+    /// write a texture type into its own directory, for example --type-dir normal=normals. Relative directories are inside the output directory. Can be repeated
+    pub type_dir: Vec<String>,
+    #[argh(option)]
+    /// a directory (or zip archive) with shared inputs, such as an env_mask used by many materials. They are used when the input directory lacks them
+    pub common_dir: Option<PathBuf>,
+    #[argh(option, default = "MatchMode::Exact")]
+    /// how file names are recognized: exact (the file name is the input name) or contains (the input name appears in the file name, like character_diffuse_4k.png). Exact by default
+    pub match_mode: MatchMode,
+    #[argh(option)]
+    /// only create these outputs, a comma separated list of texture types (normal,specular), suffixes or pack suffixes. Can be repeated
+    pub only: Vec<String>,
+    #[argh(option)]
+    /// don't create these outputs, given like --only. An output can't be in both lists
+    pub skip: Vec<String>,
+    #[argh(option)]
+    /// override the suffix of a texture type, for example --suffix normal=_normal. Can be repeated. The types are diffuse, normal, glow, skin_tint, height, cubemap, env_mask, inner, backlight, specular and ao
+    pub suffix: Vec<String>,
+    #[argh(switch)]
+    /// encode the existing mipmaps of dds inputs instead of generating them from the full resolution, keeping hand made lower mipmaps. Textures that combine the dds with other images still generate them
+    pub keep_mips: bool,
+    #[argh(option)]
+    /// stop the mipmap chain at the level whose smaller side is this many pixels, for example 4 to leave out the mipmaps smaller than a compression block. All mipmaps down to 1x1 by default
+    pub min_mip_size: Option<u32>,
+    #[argh(option)]
+    /// read the image of one input, for example diffuse, from stdin instead of the input directory
+    pub stdin: Option<String>,
+    #[argh(switch)]
+    /// write the texture to stdout instead of the output directory. The conversion has to result in exactly one texture, the messages go to stderr
+    pub stdout: bool,
+    #[argh(switch)]
+    /// encode every texture twice and fail if the results are not byte identical. The encoders and the mipmap generation are single threaded and deterministic, so the same inputs and options always give the same files
+    pub verify_reproducible: bool,
+    #[argh(switch)]
+    /// read every written dds back and decode its full resolution, reporting the files that fail as errors
+    pub verify: bool,
+    #[argh(switch)]
+    /// after writing, delete the textures of this name (name + suffix) in the output directory that this run didn't produce, such as the _g.dds of a removed glow image
+    pub clean: bool,
+    #[argh(option)]
+    /// write a text file listing the written textures in slot order as texture=path lines, for example diffuse=iron.dds. Relative to the output directory
+    pub txt_manifest: Option<PathBuf>,
+    #[argh(option)]
+    /// write a JSON file listing the written textures in slot order with their path, format, size, mipmaps, file size, uncompressed size, compression ratio and bits per pixel, for auditing the compression of a mod. Relative to the output directory
+    pub manifest: Option<PathBuf>,
+    #[argh(switch)]
+    /// also write a preview.png (name + preview.png) into the output directory with a labeled thumbnail of every written texture, decoded back from the dds, for spotting mixed up inputs at a glance
+    pub preview: bool,
+    #[argh(switch)]
+    /// print every recognized input name with the suffix of its texture and a short description, then exit without converting
+    pub list_slots: bool,
+    #[argh(switch)]
+    /// only check the inputs: list the found images with their resolutions, read from the file headers without decoding them, and report images that are combined into one texture at different resolutions. Nothing is written
+    pub dry_run: bool,
+    #[argh(switch)]
+    /// print the file size and the estimated video memory (all mipmaps at the block size of the format) of every texture and the totals before writing
+    pub footprint: bool,
+    #[argh(option)]
+    /// a shell command to run on every written texture, {{path}} is replaced by its path, for example --post-hook "sign {{path}}". The exit status of each run is reported
+    pub post_hook: Option<String>,
+    #[argh(switch)]
+    /// print how long loading, encoding each texture and writing took
+    pub timing: bool,
+}
+
+#[derive(FromArgs, Clone)]
+#[argh(subcommand, name = "backward")]
+/// convert dds textures back to images (dds -> png). It only splits off alpha channel. Keep in mind that dds is lossy, the lost detail can't be retrieved.
+pub struct BackwardArgs {
+    #[argh(option, short = 'n', default = "String::from(\"\")")]
+    /// prefix of the resulting image names
+    pub name: String,
+    #[argh(option, short = 'f', default = "OutputFormat::Png")]
+    /// format of the resulting images: png, tga, bmp or tiff. Png by default
+    pub format: OutputFormat,
+    #[argh(option, default = "PngCompression::Default")]
+    /// compression of png images: fast, default or best. Best gives the smallest files but takes the longest
+    pub png_compression: PngCompression,
+    #[argh(option, short = 'm', default = "0")]
+    /// the mipmap level to extract, 0 (full resolution) by default
+    pub mip: u32,
+    #[argh(switch)]
+    /// write a single rgba image per texture instead of splitting the alpha channel into a separate _alpha image
+    pub rgba: bool,
+    #[argh(option, default = "0")]
+    /// alpha values within this distance of 255 count as opaque when deciding whether to write the _alpha image, 0 by default
+    pub alpha_tolerance: u8,
+    #[argh(option, default = "0.0")]
+    /// the percentage of pixels that have to be below the opaque alpha for the _alpha image to be written, 0 (any pixel) by default
+    pub alpha_min_percent: f32,
+    #[argh(option)]
+    /// split every texture into a grid of rows x columns equal tiles, for example 4x8, written as name_row_column. Useful for cutting icons out of an atlas
+    pub split_grid: Option<SplitGrid>,
+    #[argh(switch, short = 't')]
+    /// the diffuse textures were created with --terrain-parallax: their alpha is written as a _height image instead of _alpha. Textures without the suffix of another slot count as diffuse
+    pub terrain_parallax: bool,
+    #[argh(option)]
+    /// override the suffix of a texture type as in forward, for example normal=_normal, so -t recognizes the diffuse textures of such a set
+    pub suffix: Vec<String>,
+    #[argh(switch)]
+    /// instead of the separate images, write a single contact_sheet.png with a labeled thumbnail of every texture, for reviewing a folder at a glance
+    pub contact_sheet: bool,
+    #[argh(option, default = "128")]
+    /// the size of the longer side of the contact sheet thumbnails in pixels, 128 by default and at least 5
+    pub thumb_size: u32,
+    #[argh(option, default = "6")]
+    /// the number of thumbnails per row of the contact sheet, 6 by default
+    pub sheet_columns: u32,
+}
+
+#[derive(FromArgs, Clone)]
+#[argh(subcommand, name = "info")]
+/// print the header details (size, mipmaps, format, array size, cubemap) of dds textures without converting anything.
+pub struct InfoArgs {
+    #[argh(positional)]
+    /// a dds file or a directory to inspect. By default all dds files in the input directory are listed
+    pub path: Option<PathBuf>,
+}
+
+#[derive(FromArgs, Clone)]
+#[argh(subcommand, name = "bench")]
+/// encode one image as BC1, BC3 and BC7 at every quality and print the file size and PSNR (peak signal to noise ratio, higher is closer to the source) of each.
+pub struct BenchArgs {
+    #[argh(positional)]
+    /// the image to encode, relative to the input directory unless it exists as given
+    pub image: PathBuf,
+}
+
+#[derive(FromArgs, Clone)]
+#[argh(subcommand, name = "test-matrix")]
+/// use one image as every input and write the textures of several option sets (default, high quality, archaic, BC3n, BC5, terrain and complex parallax, skin), named name_variant + suffix. A smoke test of the whole tool.
+pub struct TestMatrixArgs {
+    #[argh(positional)]
+    /// the test pattern, relative to the input directory unless it exists as given
+    pub image: PathBuf,
+    #[argh(option, short = 'n', default = "String::from(\"test_matrix\")")]
+    /// the start of the texture names, test_matrix by default
+    pub name: String,
+    #[argh(option, default = "settings::env_quality()")]
+    /// the encoder quality of all textures, as --quality-bc7 in forward
+    pub quality: EncodeQuality,
+}
+
+#[derive(FromArgs, Clone)]
+#[argh(subcommand, name = "organize")]
+/// copy already encoded dds textures to the names of the texture set (name + suffix) without encoding them again. Every file is checked to be a readable dds.
+pub struct OrganizeArgs {
+    #[argh(option, short = 'n', default = "String::from(\"\")")]
+    /// the name of the resulting textures, as in forward
+    pub name: String,
+    #[argh(option)]
+    /// which texture a dds file (without the .dds) is, for example --map armor_nrm=normal. Can be repeated, files that are not mapped are skipped
+    pub map: Vec<String>,
+    #[argh(option)]
+    /// override the suffix of a texture type, as in forward
+    pub suffix: Vec<String>,
+}
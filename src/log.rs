@@ -0,0 +1,145 @@
+//! Progress and error messages. They go to stdout, unless stdout carries a texture, and optionally to a log file.
+//! With --json-errors the errors are written to stderr as JSON lines instead, for scripts wrapping the tool.
+//! All output goes through one lock, so messages logged from parallel work never interleave within a message,
+//! and [grouped] keeps all messages of one piece of work together.
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+/// Held while writing, so stdout, stderr and the log file see the messages in the same order.
+static OUTPUT: Mutex<()> = Mutex::new(());
+
+/// A message waiting in a group: whether it goes to stderr, the printed text and the text for the log file.
+type Pending = (bool, String, String);
+
+thread_local! {
+    static GROUP: RefCell<Option<Vec<Pending>>> = const { RefCell::new(None) };
+}
+
+/// Sends the messages to stderr from now on.
+pub fn use_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Writes the errors as JSON lines to stderr from now on, see [error].
+pub fn use_json_errors() {
+    JSON_ERRORS.store(true, Ordering::Relaxed);
+}
+
+/// Also appends all following messages to the file, each line with a timestamp.
+pub fn open_log_file(path: &Path) -> std::io::Result<()> {
+    let file = File::options().create(true).append(true).open(path)?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// Runs the work and prints the messages it logs on this thread together once it is done,
+/// so the messages of textures created in parallel stay grouped per texture. Nested groups join the outer one.
+pub fn grouped<T>(work: impl FnOnce() -> T) -> T {
+    let outer = GROUP.with(|g| g.borrow_mut().replace(Vec::new()));
+    let res = work();
+    let nested = outer.is_some();
+    let pending = GROUP.with(|g| std::mem::replace(&mut *g.borrow_mut(), outer)).unwrap_or_default();
+    if nested {
+        GROUP.with(|g| g.borrow_mut().as_mut().unwrap().extend(pending));
+    } else {
+        let _lock = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+        for (stderr, printed, logged) in pending {
+            write(stderr, &printed, &logged);
+        }
+    }
+    res
+}
+
+/// Writes the message now, or keeps it for the end of the group running on this thread.
+fn emit(stderr: bool, printed: String, logged: &str) {
+    let buffered = GROUP.with(|g| match g.borrow_mut().as_mut() {
+        Some(pending) => {
+            pending.push((stderr, printed.clone(), logged.to_owned()));
+            true
+        }
+        None => false,
+    });
+    if !buffered {
+        let _lock = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+        write(stderr, &printed, logged);
+    }
+}
+
+fn write(stderr: bool, printed: &str, logged: &str) {
+    if stderr {
+        eprintln!("{}", printed);
+    } else {
+        println!("{}", printed);
+    }
+    append_to_file(logged);
+}
+
+/// Prints the message and appends it to the log file. Use the [log!] macro instead.
+pub fn message(text: &str) {
+    emit(TO_STDERR.load(Ordering::Relaxed), text.to_owned(), text);
+}
+
+#[derive(Serialize)]
+struct ErrorRecord<'a> {
+    /// "critical" when the conversion stops, "error" when only a part of it is skipped.
+    level: &'static str,
+    /// Where it happened: setup, load, create, verify or write.
+    stage: &'a str,
+    file: Option<&'a str>,
+    message: &'a str,
+}
+
+/// Reports an error, text starting with "Critical error" stops the conversion. Use the [error!] macro instead.
+/// The text is printed like any message, unless JSON errors are on.
+pub fn error(stage: &str, file: Option<&str>, text: &str) {
+    if !JSON_ERRORS.load(Ordering::Relaxed) {
+        message(text);
+        return;
+    }
+    let (level, rest) = match text.strip_prefix("Critical error") {
+        Some(rest) => ("critical", rest),
+        None => ("error", text.strip_prefix("Error").unwrap_or(text)),
+    };
+    let record = ErrorRecord {
+        level,
+        stage,
+        file,
+        message: rest.trim_start_matches([',', ':', ' ']),
+    };
+    emit(true, serde_json::to_string(&record).unwrap(), text);
+}
+
+fn append_to_file(text: &str) {
+    if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+        let time = humantime::format_rfc3339_seconds(SystemTime::now());
+        for line in text.lines() {
+            // a failing log file must not stop the conversion
+            let _ = writeln!(file, "{} {}", time, line);
+        }
+    }
+}
+
+/// Prints a message like println!, see [message].
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::log::message(&format!($($arg)*))
+    };
+}
+
+/// Reports an error like log!, with the stage and optionally the file it concerns, see [error].
+#[macro_export]
+macro_rules! error {
+    ($stage:expr, $file:expr, $($arg:tt)*) => {
+        $crate::log::error($stage, $file, &format!($($arg)*))
+    };
+}
@@ -1,85 +1,304 @@
 use argh::FromArgs;
 
-use std::error::Error;
-use std::path::{PathBuf};
+use std::process::ExitCode;
+use std::path::{Path, PathBuf};
 
-mod forward;
-mod backward;
-use forward::run_forward;
-use backward::run_backward;
-
-// TODO: implement complex skin material (glossiness in G channel of specular map)
+use dds_material_creator::backward::run_backward;
+use dds_material_creator::bench::run_bench;
+use dds_material_creator::forward::{list_slots, long_path, run_forward};
+use dds_material_creator::info::run_info;
+use dds_material_creator::organize::run_organize;
+use dds_material_creator::settings::{self, Settings};
+use dds_material_creator::test_matrix::run_test_matrix;
+use dds_material_creator::{error, log, BackwardArgs, BenchArgs, ForwardArgs, InfoArgs, OrganizeArgs, TestMatrixArgs};
 
 #[derive(FromArgs)]
 /// Converts image files to Skyrim textures. The resulting textures are composed and named according to the Skyrim conventions, mipmaps are generated.
 /// Input files are recognized by file names (without suffix) and all of them are optional:
-/// diffuse, normal, specular, glow, skin_tint, height, cubemap, env_mask, inner_diffuse, inner_depth, subsurface, backlight, metallic, glossiness.
+/// diffuse, normal, specular, glow, skin_tint, height, cubemap, env_mask, inner_diffuse, inner_depth, subsurface, backlight, metallic, glossiness, roughness, ao.
+/// Additional glow images named glow2, glow3, ... are added to the glow image.
+/// The subsurface image is the subsurface tint of the skin shader and shares the _sk texture with skin_tint.
 /// All textures for which the required images are provided will be generated. Images that combine into one texture must have the same resolution!
-/// The common supported formats are png, tif, jpg and bmp.
+/// The common supported formats are png, tif, jpg and bmp. Dds textures are accepted as well.
+/// Options can also be stored in a dmc.toml file in the input directory (name, high_quality, archaic_format and parallax = "none", "terrain" or "complex"), the command line overrides them.
+/// An input image can be described by a sidecar with the same stem (rock.png and rock.dmc.json) holding its "slot", "colorspace" and "format".
+/// Files matching the glob patterns listed in a .dmcignore file in the input directory are skipped.
 /// The textures used ingame depend on the meshes' property flags, just use the ones you need!
+/// Without a subcommand the forward conversion runs, the forward flags can then be given directly, as in -i my_material -n iron -h.
+/// The environment variables DMC_QUALITY, DMC_HIGH_QUALITY, DMC_ARCHAIC_FORMAT and DMC_OUTPUT_DIR set defaults, the command line overrides them.
 /// For details on supported image formats look at the default features of the image crate (https://docs.rs/image).
 /// For details on texture composition and names see Texture Slots section at https://wiki.beyondskyrim.org/wiki/Arcane_University:NIF_Data_Format.
 /// For details on complex parallax textures see https://modding.wiki/en/skyrim/developers/complex-parallax-materials
 struct Args {
-    #[argh(option, short = 'n', default = "String::from(\"\")")]
-    /// the name of the resulting textures. For example, the normal map file will be named name_n.dds
-    pub name: String,
-    #[argh(switch, short = 'h')]
-    /// force diffuse textures to use BC7 instead of BC1 (normals always use BC7). BC7 should better represent subtle changes or gradients, but uses significantly more space
-    pub high_quality: bool,
-    #[argh(switch, short = 'a')]
-    /// only use older formats (BC1 and BC3) compatible with Skyrim LE. Only use if you target games that do not support BC4 and BC7
-    pub archaic_format: bool,
-    #[argh(switch, short = 't')]
-    /// will write height information instead of transparency to the alpha channel of the diffuse texture. Used for parallax on landscape/terrain textures.
-    pub terrain_parallax: bool,
-    #[argh(switch, short = 'c')]
-    /// will write complex parallax information (R: env_mask, G: glossiness, B: metallic, A: height) into the environment map. Used for parallax on object textures.
-    pub complex_parallax: bool,
     #[argh(option, short = 'i')]
-    /// specifies the input directory. By default the current working directory is used
-    pub input_dir: Option<PathBuf>,
+    /// specifies the input directory, or a zip archive with the input images. By default the current working directory is used. Can be repeated or comma separated, then the outputs of each input go into a subfolder (named like the input) of the output directory
+    pub input_dir: Vec<PathBuf>,
+    #[argh(option)]
+    /// a text file listing input directories (or zip archives) one per line, converted in that order into subfolders of the output directory like repeated -i. Relative paths are relative to the list file, blank lines and lines starting with # are skipped
+    pub input_list: Option<PathBuf>,
     #[argh(option, short = 'o')]
-    /// specifies the output directory. By default 'output' directory is created in the input directory
+    /// specifies the output directory. By default 'output' directory is created in the input directory, or in the working directory for multiple inputs
     pub output_dir: Option<PathBuf>,
-    #[argh(switch, short = 'b')]
-    /// run the conversion backward (dds -> png). It only splits off alpha channel. Keep in mind that dds is lossy, the lost detail can't be retrieved.
-    pub backward: bool,
+    #[argh(option)]
+    /// also append all messages with timestamps to this file, for reviewing unattended batch runs
+    pub log_file: Option<PathBuf>,
+    #[argh(switch)]
+    /// write errors to stderr as JSON lines with level, stage, file and message fields instead of text, for scripts and CI
+    pub json_errors: bool,
+    #[argh(subcommand)]
+    pub command: Option<Command>,
 }
 
+#[derive(FromArgs, Clone)]
+#[argh(subcommand)]
+// argh can't parse boxed subcommands, and there is only one Command per run
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    Forward(ForwardArgs),
+    Backward(BackwardArgs),
+    Info(InfoArgs),
+    Bench(BenchArgs),
+    Organize(OrganizeArgs),
+    TestMatrix(TestMatrixArgs),
+}
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Args = argh::from_env();
-    let dir = if let Some(p) = &args.input_dir {
-        p.clone()
-    } else {
-        match std::env::current_dir() {
-            Ok(p) => p,
+
+/// The name ends up in file names, it must not contain path separators or characters Windows doesn't allow in file names.
+fn validate_name(name: &str) -> Result<(), String> {
+    if let Some(c) = name.chars().find(|c| c.is_control() || "/\\<>:\"|?*".contains(*c)) {
+        return Err(format!("the name {:?} contains {:?}, which is not allowed in file names", name, c));
+    }
+    if name.ends_with(['.', ' ']) {
+        return Err(format!("the name {:?} must not end with a dot or a space", name));
+    }
+    Ok(())
+}
+
+/// Converts one input directory. The output directory defaults to 'output' next to the inputs.
+fn convert(command: &Command, dir: &Path, output_dir: Option<PathBuf>, to_stdout: bool) -> bool {
+    let mut command = command.clone();
+    if let Command::Forward(forward_args) = &mut command {
+        match Settings::load(dir) {
+            Ok(Some(settings)) => {
+                log!("Using the settings of {}.", settings::FILE_NAME);
+                settings.apply(forward_args);
+            }
+            Ok(None) => {}
             Err(e) => {
-                println!("Critical error, Cannot access the working directory: {}", e);
-                println!("You can set input directory with the -i flag.");
-                return Ok(());
+                error!("setup", Some(settings::FILE_NAME), "Critical error, {}", e);
+                return false;
             }
         }
+    }
+    let name = match &command {
+        Command::Forward(forward_args) => Some(&forward_args.name),
+        Command::Backward(backward_args) => Some(&backward_args.name),
+        Command::Organize(organize_args) => Some(&organize_args.name),
+        Command::TestMatrix(test_matrix_args) => Some(&test_matrix_args.name),
+        Command::Info(_) | Command::Bench(_) => None,
     };
-    println!("Using input directory: {}", dir.display());
-    let mut out_dir = if let Some(p) = &args.output_dir {
-        p.clone()
-    } else {
-        dir.join("output")
+    if let Err(e) = name.map_or(Ok(()), |n| validate_name(n)) {
+        error!("setup", None, "Critical error, {}", e);
+        return false;
+    }
+    log!("Using input directory: {}", dir.display());
+    if let Command::Info(info_args) = &command {
+        return run_info(info_args, dir);
+    }
+    if let Command::Bench(bench_args) = &command {
+        return run_bench(bench_args, dir);
+    }
+    // Outputs of a zip archive go next to the archive.
+    let base_dir = match dir.parent() {
+        Some(parent) if long_path(dir).is_file() => parent.to_path_buf(),
+        _ => dir.to_path_buf(),
     };
-    if let Err(e) = std::fs::create_dir_all(out_dir.clone()) {
-        println!("Error creating output dir: {}", e);
-        println!("Will try to save in the input directory.");
-        out_dir = dir.clone();
+    let mut out_dir = output_dir.unwrap_or_else(|| base_dir.join("output"));
+    if to_stdout {
+        // nothing is written to the output directory
+    } else if let Err(e) = std::fs::create_dir_all(long_path(&out_dir)) {
+        error!("setup", None, "Error creating output dir: {}", e);
+        log!("Will try to save in the input directory.");
+        out_dir = base_dir;
     }
-    if args.backward{
-        run_backward(&args, &dir, &out_dir);
+    match &command {
+        Command::Forward(forward_args) => run_forward(forward_args, dir, &out_dir),
+        Command::Backward(backward_args) => run_backward(backward_args, dir, &out_dir),
+        Command::Organize(organize_args) => run_organize(organize_args, dir, &out_dir),
+        Command::TestMatrix(test_matrix_args) => run_test_matrix(test_matrix_args, dir, &out_dir),
+        Command::Info(_) | Command::Bench(_) => true,
     }
-    else{
-        run_forward(&args, &dir, &out_dir);
+}
+
+/// The input directories of an --input-list file.
+fn read_input_list(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let text = std::fs::read_to_string(long_path(path)).map_err(|e| format!("cannot read the input list {}: {}", path.display(), e))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect())
+}
+
+/// The name of the output subfolder of an input directory or zip archive.
+fn subfolder_name(dir: &Path) -> Option<String> {
+    let name = if long_path(dir).is_file() { dir.file_stem() } else { dir.file_name() };
+    name.and_then(|n| n.to_str()).map(|n| n.to_owned())
+}
 
+/// The options of Args that take a value, and its switches.
+const TOP_LEVEL_OPTIONS: [&str; 6] = ["-i", "--input-dir", "--input-list", "-o", "--output-dir", "--log-file"];
+const TOP_LEVEL_SWITCHES: [&str; 2] = ["--json-errors", "--help"];
+
+/// Older versions took the forward flags without the mode, as in `-i dir -n iron -h`. When the command line has flags
+/// that are neither top level options nor a mode, `forward` is inserted before them and the top level options are moved in front of it.
+fn with_default_mode(args: &[String]) -> Vec<String> {
+    let is_mode = |arg: &str| <Command as argh::SubCommands>::COMMANDS.iter().any(|c| c.name == arg);
+    let (mut top, mut forward): (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if TOP_LEVEL_OPTIONS.contains(&arg.as_str()) {
+            top.push(arg.clone());
+            top.extend(args.next().cloned());
+        } else if forward.is_empty() && TOP_LEVEL_SWITCHES.contains(&arg.as_str()) {
+            top.push(arg.clone());
+        } else if forward.is_empty() && is_mode(arg) {
+            // an explicit mode, the command line is parsed as it is
+            top.push(arg.clone());
+            top.extend(args.cloned());
+            return top;
+        } else {
+            forward.push(arg.clone());
+        }
     }
+    if !forward.is_empty() {
+        top.push(String::from("forward"));
+        top.append(&mut forward);
+    }
+    top
+}
 
-    Ok(())
+/// Like argh::from_env, with the mode defaulting to forward.
+fn parse_args() -> Args {
+    let strings: Vec<String> = std::env::args_os()
+        .map(|s| s.into_string())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|arg| {
+            eprintln!("Invalid utf8: {}", arg.to_string_lossy());
+            std::process::exit(1)
+        });
+    let Some((program, args)) = strings.split_first() else {
+        eprintln!("No program name, argv is empty");
+        std::process::exit(1)
+    };
+    let cmd = Path::new(program).file_name().and_then(|s| s.to_str()).unwrap_or(program);
+    let args = with_default_mode(args);
+    let strs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    Args::from_args(&[cmd], &strs).unwrap_or_else(|early_exit| match early_exit.status {
+        Ok(()) => {
+            println!("{}", early_exit.output);
+            std::process::exit(0)
+        }
+        Err(()) => {
+            eprintln!("{}\nRun {} --help for more information.", early_exit.output, cmd);
+            std::process::exit(1)
+        }
+    })
+}
+
+fn main() -> ExitCode {
+    let args = parse_args();
+    // parsed like an empty forward command line, so the defaults match the explicit subcommand
+    let mut command = args
+        .command
+        .unwrap_or_else(|| Command::Forward(ForwardArgs::from_args(&["forward"], &[]).unwrap_or_default()));
+    if let Command::Forward(forward_args) = &mut command {
+        settings::apply_env(forward_args);
+    }
+    let output_dir = args.output_dir.clone().or_else(settings::env_output_dir);
+    let to_stdout = matches!(&command, Command::Forward(f) if f.stdout);
+    if to_stdout {
+        dds_material_creator::log::use_stderr();
+    }
+    if args.json_errors {
+        dds_material_creator::log::use_json_errors();
+    }
+    if let Some(path) = &args.log_file {
+        if let Err(e) = dds_material_creator::log::open_log_file(path) {
+            error!("setup", None, "Critical error, cannot open the log file {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+    let mut dirs: Vec<PathBuf> = args
+        .input_dir
+        .iter()
+        .flat_map(|p| p.to_string_lossy().split(',').map(PathBuf::from).collect::<Vec<_>>())
+        .filter(|p| !p.as_os_str().is_empty())
+        .collect();
+    if let Some(list) = &args.input_list {
+        match read_input_list(list) {
+            Ok(listed) if listed.is_empty() => {
+                error!("setup", Some(list.display().to_string().as_str()), "Critical error, the input list {} names no directories!", list.display());
+                return ExitCode::FAILURE;
+            }
+            Ok(listed) => dirs.extend(listed),
+            Err(e) => {
+                error!("setup", Some(list.display().to_string().as_str()), "Critical error, {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if dirs.is_empty() {
+        match std::env::current_dir() {
+            Ok(p) => dirs.push(p),
+            Err(e) => {
+                error!("setup", None, "Critical error, Cannot access the working directory: {}", e);
+                log!("You can set input directory with the -i flag.");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Command::Forward(forward_args) = &command {
+        if forward_args.list_slots {
+            list_slots(forward_args);
+            return ExitCode::SUCCESS;
+        }
+    }
+    // the directories of a list always get subfolders, so the layout doesn't change with the length of the list
+    let success = if let ([dir], None) = (dirs.as_slice(), &args.input_list) {
+        convert(&command, dir, output_dir.clone(), to_stdout)
+    } else {
+        if to_stdout {
+            error!("setup", None, "Critical error, --stdout takes a single input directory!");
+            return ExitCode::FAILURE;
+        }
+        // every input gets its own subfolder, so equal texture names don't overwrite each other
+        let out_root = output_dir.clone().unwrap_or_else(|| PathBuf::from("output"));
+        let mut subfolders: Vec<String> = Vec::new();
+        for dir in &dirs {
+            match subfolder_name(dir) {
+                Some(name) if !subfolders.contains(&name) => subfolders.push(name),
+                Some(name) => {
+                    error!("setup", None, "Critical error, two input directories are named {}, their outputs would mix!", name);
+                    return ExitCode::FAILURE;
+                }
+                None => {
+                    error!("setup", None, "Critical error, cannot name the output folder of {}!", dir.display());
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        let mut success = true;
+        for (dir, subfolder) in dirs.iter().zip(subfolders) {
+            success &= convert(&command, dir, Some(out_root.join(subfolder)), to_stdout);
+        }
+        success
+    };
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
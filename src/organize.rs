@@ -0,0 +1,74 @@
+//! Copies existing dds textures to the names of the texture set without encoding them again.
+use image_dds::ddsfile::Dds;
+use std::fs::File;
+use std::path::Path;
+
+use crate::backward::get_dds_file_paths;
+use crate::forward::{long_path, write_atomically};
+use crate::slots::{Suffixes, TextureType, SLOTS};
+use crate::OrganizeArgs;
+
+/// Parses the mapping entries old_name=texture, for example armor_nrm=normal.
+fn parse_mapping(entries: &[String]) -> Result<Vec<(String, TextureType)>, String> {
+    let mut mapping: Vec<(String, TextureType)> = Vec::new();
+    for entry in entries {
+        let (file, name) = entry
+            .split_once('=')
+            .ok_or(format!("invalid mapping {}, expected file=texture, for example armor_nrm=normal", entry))?;
+        let texture = TextureType::from_name(name.trim()).ok_or(format!(
+            "unknown texture {} in mapping, expected one of: {}",
+            name,
+            SLOTS.map(|s| s.name).join(", ")
+        ))?;
+        if let Some((other, _)) = mapping.iter().find(|(_, t)| *t == texture) {
+            return Err(format!("{} and {} are both mapped to {}", other, file.trim(), name.trim()));
+        }
+        mapping.push((file.trim().to_owned(), texture));
+    }
+    Ok(mapping)
+}
+
+/// Returns false when a critical error stopped the organizing.
+pub fn run_organize(args: &OrganizeArgs, in_dir: &Path, out_dir: &Path) -> bool {
+    let suffixes = match Suffixes::new(&args.suffix) {
+        Ok(s) => s,
+        Err(e) => {error!("setup", None, "Critical error, {}", e); return false;},
+    };
+    let mapping = match parse_mapping(&args.map) {
+        Ok(m) => m,
+        Err(e) => {error!("setup", None, "Critical error, {}", e); return false;},
+    };
+    let paths = match get_dds_file_paths(in_dir) {
+        Ok(paths) => paths,
+        Err(e) => {error!("setup", None, "Critical error, cannot get file paths: {}", e); return false;},
+    };
+    let mut names: Vec<&String> = paths.keys().collect();
+    names.sort();
+    for name in names {
+        let path = &paths[name];
+        let Some((_, texture)) = mapping.iter().find(|(file, _)| file == name) else {
+            log!("Skipping {}, it is not in the mapping.", path.display());
+            continue;
+        };
+        // only files that are valid textures get the name of one
+        if let Err(e) = File::open(long_path(path)).map_err(|e| e.to_string()).and_then(|f| Dds::read(f).map_err(|e| e.to_string())) {
+            error!("load", Some(path.display().to_string().as_str()), "Error, {} is not a readable dds: {}", path.display(), e);
+            continue;
+        }
+        let out_path = out_dir.join(args.name.clone() + suffixes.get(*texture) + ".dds");
+        log!("Copying: {} to {}", path.display(), out_path.display());
+        let copied = write_atomically(&out_path, |file| {
+            let mut source = File::open(long_path(path)).map_err(|e| e.to_string())?;
+            std::io::copy(&mut source, file).map(|_| ()).map_err(|e| e.to_string())
+        });
+        if let Err(e) = copied {
+            error!("write", Some(out_path.display().to_string().as_str()), "Error, cannot write {}! {}", out_path.display(), e);
+        }
+    }
+    for (file, _) in &mapping {
+        if !paths.contains_key(file) {
+            log!("Warning: {}.dds of the mapping was not found.", file);
+        }
+    }
+    true
+}
@@ -0,0 +1,118 @@
+//! Per-material settings read from a dmc.toml file in the input directory, so the conversion options can be kept next to the art,
+//! and defaults read from DMC_* environment variables for build containers.
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::forward::{long_path, EncodeQuality};
+use crate::ForwardArgs;
+
+/// The name of the settings file in the input directory.
+pub const FILE_NAME: &str = "dmc.toml";
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Parallax {
+    None,
+    Terrain,
+    Complex,
+}
+
+/// The options of a dmc.toml file, for example:
+/// ```toml
+/// name = "iron_armor"
+/// high_quality = true
+/// parallax = "complex"
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub name: Option<String>,
+    pub high_quality: Option<bool>,
+    pub archaic_format: Option<bool>,
+    pub parallax: Option<Parallax>,
+}
+
+impl Settings {
+    /// Reads the settings file of the input directory, None if there is none.
+    pub fn load(in_dir: &Path) -> Result<Option<Settings>, String> {
+        let path = in_dir.join(FILE_NAME);
+        if !long_path(&path).is_file() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(long_path(&path)).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text)
+            .map(Some)
+            .map_err(|e| format!("invalid {}: {}", path.display(), e))
+    }
+
+    /// Fills the options that were not given on the command line. Switches can only be turned on from the command line,
+    /// so a switch that is set wins over the file, and the file's parallax is only used when neither -t nor -c is given.
+    pub fn apply(&self, args: &mut ForwardArgs) {
+        if let Some(name) = &self.name {
+            if args.name.is_empty() {
+                args.name = name.clone();
+            }
+        }
+        args.high_quality |= self.high_quality.unwrap_or(false);
+        args.archaic_format |= self.archaic_format.unwrap_or(false);
+        if let Some(parallax) = self.parallax {
+            if !args.terrain_parallax && !args.complex_parallax {
+                args.terrain_parallax = parallax == Parallax::Terrain;
+                args.complex_parallax = parallax == Parallax::Complex;
+            }
+        }
+    }
+}
+
+/// The extension of the sidecar files next to input images, rock.png is described by rock.dmc.json.
+pub const SIDECAR_EXTENSION: &str = ".dmc.json";
+
+/// Hints for one input image written by art tooling, for example:
+/// ```json
+/// { "slot": "normal", "colorspace": "linear", "format": "BC5_UNORM" }
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Sidecar {
+    /// The input the image is used as, instead of the one matching its file name.
+    pub slot: Option<String>,
+    /// The color space of the texture the image ends up in, as in --colorspace.
+    pub colorspace: Option<String>,
+    /// The format of the texture the image ends up in, as in --dxgi-format.
+    pub format: Option<String>,
+}
+
+impl Sidecar {
+    pub fn parse(text: &str) -> Result<Sidecar, String> {
+        serde_json::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+/// The encoder quality from DMC_QUALITY, the default of --quality-bc7 and --quality-other. Slow without it.
+/// It is the default of several flags, so the variable is parsed and an invalid one reported only once.
+pub fn env_quality() -> EncodeQuality {
+    static QUALITY: OnceLock<EncodeQuality> = OnceLock::new();
+    *QUALITY.get_or_init(|| match std::env::var("DMC_QUALITY") {
+        Ok(v) => v.parse().unwrap_or_else(|e| {
+            log!("Warning: Ignoring DMC_QUALITY, {}.", e);
+            EncodeQuality::Slow
+        }),
+        Err(_) => EncodeQuality::Slow,
+    })
+}
+
+/// The output directory from DMC_OUTPUT_DIR, used when -o is not given.
+pub fn env_output_dir() -> Option<PathBuf> {
+    std::env::var_os("DMC_OUTPUT_DIR").filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// Turns on the switches set by DMC_HIGH_QUALITY and DMC_ARCHAIC_FORMAT (1, true or yes).
+pub fn apply_env(args: &mut ForwardArgs) {
+    args.high_quality |= env_flag("DMC_HIGH_QUALITY");
+    args.archaic_format |= env_flag("DMC_ARCHAIC_FORMAT");
+}
@@ -0,0 +1,159 @@
+//! The texture slots of a Skyrim texture set and the file suffixes used for them.
+//! See the Texture Slots section at https://wiki.beyondskyrim.org/wiki/Arcane_University:NIF_Data_Format
+
+use std::collections::HashMap;
+
+/// The kinds of textures this tool produces.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TextureType {
+    Diffuse,
+    Normal,
+    Glow,
+    SubsurfaceTint,
+    Height,
+    Cubemap,
+    EnvMask,
+    Inner,
+    Backlight,
+    Specular,
+    AmbientOcclusion,
+}
+
+pub struct SlotInfo {
+    pub texture: TextureType,
+    /// The name used to refer to the texture on the command line.
+    pub name: &'static str,
+    /// Index of the slot in BSShaderTextureSet, if the game has one for this texture.
+    pub slot: Option<u8>,
+    pub suffix: &'static str,
+}
+
+/// Slots 2 and 7 are shared: which texture the game reads from them depends on the shader flags of the mesh.
+pub const SLOTS: [SlotInfo; 11] = [
+    SlotInfo { texture: TextureType::Diffuse, name: "diffuse", slot: Some(0), suffix: "" },
+    SlotInfo { texture: TextureType::Normal, name: "normal", slot: Some(1), suffix: "_n" },
+    SlotInfo { texture: TextureType::Glow, name: "glow", slot: Some(2), suffix: "_g" },
+    SlotInfo { texture: TextureType::SubsurfaceTint, name: "skin_tint", slot: Some(2), suffix: "_sk" },
+    SlotInfo { texture: TextureType::Height, name: "height", slot: Some(3), suffix: "_p" },
+    SlotInfo { texture: TextureType::Cubemap, name: "cubemap", slot: Some(4), suffix: "_e" },
+    SlotInfo { texture: TextureType::EnvMask, name: "env_mask", slot: Some(5), suffix: "_m" },
+    SlotInfo { texture: TextureType::Inner, name: "inner", slot: Some(6), suffix: "_i" },
+    SlotInfo { texture: TextureType::Backlight, name: "backlight", slot: Some(7), suffix: "_b" },
+    SlotInfo { texture: TextureType::Specular, name: "specular", slot: Some(7), suffix: "_s" },
+    SlotInfo { texture: TextureType::AmbientOcclusion, name: "ao", slot: None, suffix: "_ao" },
+];
+
+/// An input image recognized by its file name.
+pub struct InputInfo {
+    pub name: &'static str,
+    /// The texture the image ends up in.
+    pub texture: TextureType,
+    pub description: &'static str,
+}
+
+pub const INPUTS: [InputInfo; 16] = [
+    InputInfo { name: "diffuse", texture: TextureType::Diffuse, description: "base color, the alpha is kept for transparency" },
+    InputInfo { name: "normal", texture: TextureType::Normal, description: "tangent space normal map" },
+    InputInfo { name: "specular", texture: TextureType::Specular, description: "specular strength, also packed into the alpha of the normal map" },
+    InputInfo { name: "glow", texture: TextureType::Glow, description: "emissive color, glow2, glow3, ... are added to it" },
+    InputInfo { name: "skin_tint", texture: TextureType::SubsurfaceTint, description: "skin tint color of the face tint shader" },
+    InputInfo { name: "height", texture: TextureType::Height, description: "height for parallax, also packed into the diffuse (-t) or environment mask (-c) alpha" },
+    InputInfo { name: "cubemap", texture: TextureType::Cubemap, description: "environment map for reflections" },
+    InputInfo { name: "env_mask", texture: TextureType::EnvMask, description: "strength of the environment map reflections" },
+    InputInfo { name: "inner_diffuse", texture: TextureType::Inner, description: "color of the inner layer of the multilayer parallax shader" },
+    InputInfo { name: "inner_depth", texture: TextureType::Inner, description: "depth of the inner layer, packed into the inner texture alpha" },
+    InputInfo { name: "subsurface", texture: TextureType::SubsurfaceTint, description: "subsurface tint of the skin shader" },
+    InputInfo { name: "backlight", texture: TextureType::Backlight, description: "color of the light shining through the surface" },
+    InputInfo { name: "metallic", texture: TextureType::EnvMask, description: "metalness, packed into the complex parallax (-c) environment mask blue" },
+    InputInfo { name: "glossiness", texture: TextureType::EnvMask, description: "glossiness, packed into the complex parallax (-c) environment mask green" },
+    InputInfo { name: "roughness", texture: TextureType::Specular, description: "roughness, inverted into the skin specular (--skin-specular) green when there is no glossiness" },
+    InputInfo { name: "ao", texture: TextureType::AmbientOcclusion, description: "ambient occlusion, can be generated from height with --gen-ao" },
+];
+
+impl TextureType {
+    pub fn info(&self) -> &'static SlotInfo {
+        SLOTS.iter().find(|s| s.texture == *self).unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<TextureType> {
+        SLOTS.iter().find(|s| s.name == name).map(|s| s.texture)
+    }
+}
+
+/// A texture file written by the forward conversion.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Output {
+    Slot(TextureType),
+    /// A custom channel pack (--pack), with its suffix.
+    Pack(String),
+}
+
+impl Output {
+    pub fn name(&self) -> String {
+        match self {
+            Output::Slot(texture) => texture.info().name.to_owned(),
+            Output::Pack(suffix) => format!("pack {}", suffix),
+        }
+    }
+
+    pub fn slot(&self) -> Option<u8> {
+        match self {
+            Output::Slot(texture) => texture.info().slot,
+            Output::Pack(_) => None,
+        }
+    }
+
+    pub fn suffix<'a>(&'a self, suffixes: &'a Suffixes) -> &'a str {
+        match self {
+            Output::Slot(texture) => suffixes.get(*texture),
+            Output::Pack(suffix) => suffix,
+        }
+    }
+}
+
+/// The suffixes of the output files, the defaults from [SLOTS] with the user overrides applied.
+pub struct Suffixes(HashMap<TextureType, String>);
+
+impl Suffixes {
+    /// Parses overrides in the form name=suffix, for example normal=_normal.
+    pub fn new(overrides: &[String]) -> Result<Suffixes, String> {
+        let mut suffixes: HashMap<TextureType, String> = SLOTS
+            .iter()
+            .map(|s| (s.texture, s.suffix.to_owned()))
+            .collect();
+        for o in overrides {
+            let (name, suffix) = o
+                .split_once('=')
+                .ok_or(format!("invalid suffix override {}, expected name=suffix", o))?;
+            let texture = TextureType::from_name(name.trim()).ok_or(format!(
+                "unknown texture {} in suffix override, expected one of: {}",
+                name,
+                SLOTS.map(|s| s.name).join(", ")
+            ))?;
+            if suffix.contains(['/', '\\']) {
+                return Err(format!("the suffix {} must not contain path separators", suffix));
+            }
+            suffixes.insert(texture, suffix.trim().to_owned());
+        }
+        let mut used: HashMap<&str, &str> = HashMap::new();
+        for slot in SLOTS.iter() {
+            let suffix = suffixes[&slot.texture].as_str();
+            if let Some(other) = used.insert(suffix, slot.name) {
+                return Err(format!(
+                    "{} and {} would both use the suffix \"{}\"",
+                    other, slot.name, suffix
+                ));
+            }
+        }
+        Ok(Suffixes(suffixes))
+    }
+
+    pub fn get(&self, texture: TextureType) -> &str {
+        &self.0[&texture]
+    }
+
+    /// The texture type already using the suffix, if any.
+    pub fn used_by(&self, suffix: &str) -> Option<TextureType> {
+        self.0.iter().find(|(_, s)| s.as_str() == suffix).map(|(t, _)| *t)
+    }
+}
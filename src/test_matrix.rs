@@ -0,0 +1,78 @@
+//! Feeds one test image into every input and writes the textures of several option sets, a smoke test of a build of the tool.
+use argh::FromArgs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::forward::{create_textures, dds_file_bytes, describe_texture, long_path, write_atomically, InputImages, OutputFilter, Timings};
+use crate::slots::{Suffixes, INPUTS};
+use crate::{ForwardArgs, TestMatrixArgs};
+
+/// Turns on the options of one variant.
+type Variant = fn(&mut ForwardArgs);
+
+/// The option sets the textures are created with, each covers code paths the default options don't reach.
+const VARIANTS: [(&str, Variant); 8] = [
+    ("default", |_| {}),
+    ("high_quality", |args| args.high_quality = true),
+    ("archaic", |args| args.archaic_format = true),
+    ("bc3n", |args| {
+        args.archaic_format = true;
+        args.bc3n = true;
+    }),
+    ("bc5", |args| args.normal_bc5 = true),
+    ("terrain", |args| args.terrain_parallax = true),
+    ("complex", |args| args.complex_parallax = true),
+    ("skin", |args| args.skin_specular = true),
+];
+
+pub fn run_test_matrix(args: &TestMatrixArgs, in_dir: &Path, out_dir: &Path) -> bool {
+    let path = if args.image.is_absolute() || args.image.exists() {
+        args.image.clone()
+    } else {
+        in_dir.join(&args.image)
+    };
+    let source = match image::open(long_path(&path)) {
+        Ok(img) => img,
+        Err(e) => {
+            error!("load", Some(path.display().to_string().as_str()), "Critical error, cannot open {}: {}", path.display(), e);
+            return false;
+        }
+    };
+    log!("Using {} ({}x{}) as every input.", path.display(), source.width(), source.height());
+    let mut images = InputImages::default();
+    for input in INPUTS.iter() {
+        if let Some(slot) = images.get_mut(input.name) {
+            *slot = Some(source.clone());
+        }
+    }
+    // parsed like an empty forward command line, so the variants start from the defaults of the tool
+    let mut defaults = ForwardArgs::from_args(&["forward"], &[]).unwrap_or_default();
+    defaults.quality_bc7 = args.quality;
+    defaults.quality_other = args.quality;
+    let suffixes = Suffixes::new(&[]).expect("the default suffixes are valid");
+    let mut success = true;
+    for (variant, apply) in VARIANTS {
+        let mut forward_args = defaults.clone();
+        apply(&mut forward_args);
+        let filter = match OutputFilter::new(&forward_args, &suffixes) {
+            Ok(f) => f,
+            Err(e) => {error!("setup", None, "Critical error, {}", e); return false;},
+        };
+        log!("Creating the {} variant.", variant);
+        let textures = create_textures(&images, &forward_args, &filter, &mut Timings::default());
+        if textures.is_empty() {
+            error!("create", None, "Error: The {} variant created no textures!", variant);
+            success = false;
+        }
+        for (texture, tex) in textures {
+            let out_path = out_dir.join(format!("{}_{}{}.dds", args.name, variant, texture.suffix(&suffixes)));
+            log!("Writing: {} ({})", out_path.display(), describe_texture(&tex));
+            let bytes = dds_file_bytes(&tex, &forward_args);
+            if let Err(e) = write_atomically(&out_path, |file| file.write_all(&bytes).map_err(|e| e.to_string())) {
+                error!("write", Some(out_path.display().to_string().as_str()), "Error writing {}: {}", out_path.display(), e);
+                success = false;
+            }
+        }
+    }
+    success
+}
@@ -0,0 +1,33 @@
+//! The contact sheet of the backward conversion at thumbnail sizes too small for the labels.
+use argh::FromArgs;
+use dds_material_creator::backward::run_backward;
+use dds_material_creator::forward::run_forward;
+use dds_material_creator::{BackwardArgs, ForwardArgs};
+use image::{Rgb, RgbImage};
+
+#[test]
+fn tiny_thumbnails() {
+    let dir = std::env::temp_dir().join(format!("dmc_contact_sheet_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let textures = dir.join("textures");
+    std::fs::create_dir_all(&textures).unwrap();
+    RgbImage::from_pixel(8, 8, Rgb([200, 100, 50])).save(dir.join("diffuse.png")).unwrap();
+    let forward = ForwardArgs::from_args(&["forward"], &["-n", "a_long_texture_name"]).unwrap();
+    assert!(run_forward(&forward, &dir, &textures));
+    for size in ["5", "6", "11"] {
+        let out_dir = dir.join(format!("sheet_{}", size));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let backward = BackwardArgs::from_args(&["backward"], &["--contact-sheet", "--thumb-size", size]).unwrap();
+        assert!(run_backward(&backward, &textures, &out_dir), "no contact sheet at the thumbnail size {}", size);
+        assert!(out_dir.join("contact_sheet.png").is_file());
+    }
+    // below the width of a glyph the size is rejected instead of drawing the labels outside the sheet
+    let rejected = dir.join("rejected");
+    std::fs::create_dir_all(&rejected).unwrap();
+    for size in ["1", "2", "4"] {
+        let backward = BackwardArgs::from_args(&["backward"], &["--contact-sheet", "--thumb-size", size]).unwrap();
+        assert!(!run_backward(&backward, &textures, &rejected), "the thumbnail size {} was accepted", size);
+        assert!(!rejected.join("contact_sheet.png").exists());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,133 @@
+//! Converts synthetic images to dds textures and back, the result has to stay within the compression tolerance of the format.
+use dds_material_creator::forward::{create_diffuse, create_generic, ImageProps, InputImages};
+use dds_material_creator::ForwardArgs;
+use image::{DynamicImage, RgbaImage};
+use image_dds::ddsfile::Dds;
+
+const SIZE: u32 = 64;
+
+/// Smooth gradients in every channel, the kind of content block compression handles well.
+fn gradient(alpha: bool) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+        let a = if alpha { ((x + y) * 2) as u8 } else { 255 };
+        image::Rgba([(x * 4) as u8, (y * 4) as u8, 255 - (x * 2) as u8, a])
+    }))
+}
+
+fn psnr(expected: &RgbaImage, actual: &RgbaImage, channels: usize) -> f64 {
+    assert_eq!(expected.dimensions(), actual.dimensions());
+    let mut sum = 0.0;
+    for (p, q) in expected.pixels().zip(actual.pixels()) {
+        for c in 0..channels {
+            let d = p.0[c] as f64 - q.0[c] as f64;
+            sum += d * d;
+        }
+    }
+    let mse = sum / (expected.len() / 4 * channels) as f64;
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+fn decode(tex: &Dds) -> RgbaImage {
+    image_dds::image_from_dds(tex, 0).unwrap()
+}
+
+fn assert_round_trip(tex: Option<Dds>, source: &DynamicImage, channels: usize, min_psnr: f64) {
+    let tex = tex.expect("no texture was created");
+    let res = psnr(&source.to_rgba8(), &decode(&tex), channels);
+    assert!(res >= min_psnr, "psnr {:.1} dB is below {} dB", res, min_psnr);
+}
+
+#[test]
+fn diffuse_bc1() {
+    let source = gradient(false);
+    let images = InputImages {
+        diffuse_alpha: Some(source.clone()),
+        ..Default::default()
+    };
+    assert_round_trip(create_diffuse(&images, &ForwardArgs::default()), &source, 3, 30.0);
+}
+
+#[test]
+fn diffuse_alpha_bc7() {
+    let source = gradient(true);
+    let images = InputImages {
+        diffuse_alpha: Some(source.clone()),
+        ..Default::default()
+    };
+    assert_round_trip(create_diffuse(&images, &ForwardArgs::default()), &source, 4, 38.0);
+}
+
+#[test]
+fn diffuse_alpha_bc3() {
+    let source = gradient(true);
+    let images = InputImages {
+        diffuse_alpha: Some(source.clone()),
+        ..Default::default()
+    };
+    let args = ForwardArgs {
+        archaic_format: true,
+        ..Default::default()
+    };
+    assert_round_trip(create_diffuse(&images, &args), &source, 4, 30.0);
+}
+
+#[test]
+fn diffuse_cutout_bc1() {
+    // a circle cut out of the gradient, the edge runs through the compression blocks
+    let mut source = gradient(false).to_rgba8();
+    for (x, y, p) in source.enumerate_pixels_mut() {
+        let (dx, dy) = (x as i32 - 32, y as i32 - 32);
+        p.0[3] = if dx * dx + dy * dy < 20 * 20 { 255 } else { 0 };
+    }
+    let source = DynamicImage::ImageRgba8(source);
+    let tex = create_diffuse(&InputImages { diffuse_alpha: Some(source.clone()), ..Default::default() }, &ForwardArgs::default());
+    let tex = tex.expect("no texture was created");
+    assert!(matches!(tex.get_dxgi_format(), Some(image_dds::ddsfile::DxgiFormat::BC1_UNorm)));
+    let decoded = decode(&tex);
+    for (p, q) in source.to_rgba8().pixels().zip(decoded.pixels()) {
+        assert_eq!(p.0[3], q.0[3], "the cutout mask changed");
+    }
+    // transparent BC1 pixels are black
+    let mut expected = source.to_rgba8();
+    for p in expected.pixels_mut().filter(|p| p.0[3] == 0) {
+        p.0 = [0, 0, 0, 0];
+    }
+    assert_round_trip(Some(tex), &DynamicImage::ImageRgba8(expected), 3, 30.0);
+}
+
+#[test]
+fn generic_bc4() {
+    let source = DynamicImage::ImageLuma8(image::GrayImage::from_fn(SIZE, SIZE, |x, y| {
+        image::Luma([((x + y) * 2) as u8])
+    }));
+    let tex = create_generic(&Some(source.clone()), None, ImageProps::Grayscale, &ForwardArgs::default());
+    assert_round_trip(tex, &source, 1, 40.0);
+}
+
+#[test]
+fn generic_rgb_bc7() {
+    let source = gradient(false);
+    let args = ForwardArgs {
+        high_quality: true,
+        ..Default::default()
+    };
+    assert_round_trip(create_generic(&Some(source.clone()), None, ImageProps::RGB, &args), &source, 3, 38.0);
+}
+
+#[test]
+fn glow_stray_alpha_bc1() {
+    // the alpha of a glow is not used, a stray one must not make its colors transparent black
+    let mut source = gradient(false).to_rgba8();
+    for (x, _, p) in source.enumerate_pixels_mut() {
+        p.0[3] = if x < SIZE / 2 { 0 } else { 255 };
+    }
+    let source = DynamicImage::ImageRgba8(source);
+    let tex = create_generic(&Some(source.clone()), None, ImageProps::RGB, &ForwardArgs::default());
+    let tex = tex.expect("no texture was created");
+    assert!(matches!(tex.get_dxgi_format(), Some(image_dds::ddsfile::DxgiFormat::BC1_UNorm)));
+    assert!(decode(&tex).pixels().all(|p| p.0[3] == 255), "the glow got transparent pixels");
+    assert_round_trip(Some(tex), &source, 3, 30.0);
+}
@@ -0,0 +1,72 @@
+//! The texture slots and suffixes against the Texture Slots table of BSShaderTextureSet at
+//! https://wiki.beyondskyrim.org/wiki/Arcane_University:NIF_Data_Format
+use argh::FromArgs;
+use dds_material_creator::forward::run_forward;
+use dds_material_creator::slots::{TextureType, SLOTS};
+use dds_material_creator::ForwardArgs;
+use image::{Rgb, RgbImage};
+use image_dds::ddsfile::Dds;
+use std::path::PathBuf;
+
+#[test]
+fn slots_match_the_wiki() {
+    let expected = [
+        (TextureType::Diffuse, Some(0), ""),
+        (TextureType::Normal, Some(1), "_n"),
+        (TextureType::Glow, Some(2), "_g"),
+        (TextureType::SubsurfaceTint, Some(2), "_sk"),
+        (TextureType::Height, Some(3), "_p"),
+        (TextureType::Cubemap, Some(4), "_e"),
+        (TextureType::EnvMask, Some(5), "_m"),
+        (TextureType::Inner, Some(6), "_i"),
+        (TextureType::Backlight, Some(7), "_b"),
+        (TextureType::Specular, Some(7), "_s"),
+        (TextureType::AmbientOcclusion, None, "_ao"),
+    ];
+    assert_eq!(SLOTS.len(), expected.len(), "a texture type is missing from the expected slots");
+    for (texture, slot, suffix) in expected {
+        let info = texture.info();
+        assert_eq!(info.slot, slot, "the slot of {}", info.name);
+        assert_eq!(info.suffix, suffix, "the suffix of {}", info.name);
+    }
+}
+
+/// A fresh directory holding the given single colored 8x8 inputs.
+fn input_dir(test: &str, images: &[(&str, [u8; 3])]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dmc_slots_{}_{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, color) in images {
+        RgbImage::from_pixel(8, 8, Rgb(*color)).save(dir.join(format!("{}.png", name))).unwrap();
+    }
+    dir
+}
+
+/// Converts the inputs and returns the color of the written subsurface tint texture.
+fn subsurface_tint_color(test: &str, images: &[(&str, [u8; 3])]) -> [u8; 3] {
+    let dir = input_dir(test, images);
+    let out_dir = dir.join("output");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let args = ForwardArgs::from_args(&["forward"], &["-n", "t"]).unwrap();
+    assert!(run_forward(&args, &dir, &out_dir));
+    let tex = Dds::read(std::fs::File::open(out_dir.join("t_sk.dds")).expect("no _sk texture was written")).unwrap();
+    let p = image_dds::image_from_dds(&tex, 0).unwrap().get_pixel(4, 4).0;
+    std::fs::remove_dir_all(&dir).unwrap();
+    [p[0], p[1], p[2]]
+}
+
+fn is_close(actual: [u8; 3], expected: [u8; 3]) -> bool {
+    actual.iter().zip(expected).all(|(a, e)| a.abs_diff(e) <= 8)
+}
+
+#[test]
+fn subsurface_goes_into_sk() {
+    let color = subsurface_tint_color("subsurface", &[("subsurface", [40, 200, 90])]);
+    assert!(is_close(color, [40, 200, 90]), "the _sk texture is {:?}", color);
+}
+
+#[test]
+fn skin_tint_wins_over_subsurface() {
+    let color = subsurface_tint_color("both", &[("skin_tint", [220, 30, 30]), ("subsurface", [30, 30, 220])]);
+    assert!(is_close(color, [220, 30, 30]), "the _sk texture is {:?}, not the skin_tint", color);
+}